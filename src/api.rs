@@ -6,27 +6,161 @@
 //! - Updating task completion status
 //! - Offline caching and sync logic
 
+use crate::backend::Backend;
 use chrono::{Local, TimeZone};
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+/// Where the Todoist API lives unless `TodoistClient::with_base_url` says
+/// otherwise.
+pub const DEFAULT_BASE_URL: &str = "https://api.todoist.com/api/v1";
+
+/// Upper bound on `TodoistClient::with_page_limit`, set by the API.
+pub const MAX_PAGE_LIMIT: usize = 200;
+
+/// Why a Todoist API call failed.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The API token is missing, invalid or revoked (401).
+    Unauthorized,
+    /// Too many requests (429); Todoist may say how long to back off.
+    RateLimited { retry_after: Option<Duration> },
+    /// The task, project or endpoint doesn't exist (404).
+    NotFound,
+    /// Todoist refused the request as invalid or not permitted (any other
+    /// 4xx, including 403 for a resource the token can't touch).
+    Rejected { status: u16, message: String },
+    /// Todoist failed to handle the request (5xx).
+    Server { status: u16, message: String },
+    /// Todoist couldn't be reached, or the connection broke mid-request.
+    Network(reqwest::Error),
+    /// The response didn't have the shape we expected.
+    Decode(String),
+    /// A Sync API command was refused.
+    Command(String),
+    /// The local task file couldn't be read or written.
+    Storage(String),
+}
+
+impl ApiError {
+    /// Whether the same request may well succeed later, so local changes
+    /// should be kept rather than rolled back.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ApiError::Network(_)
+                | ApiError::RateLimited { .. }
+                | ApiError::Server { .. }
+                | ApiError::Unauthorized
+        )
+    }
+
+    /// Classify a non-2xx response.
+    fn from_status(status: StatusCode, retry_after: Option<Duration>, message: String) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ApiError::Unauthorized,
+            StatusCode::TOO_MANY_REQUESTS => ApiError::RateLimited { retry_after },
+            StatusCode::NOT_FOUND => ApiError::NotFound,
+            s if s.is_server_error() => ApiError::Server {
+                status: s.as_u16(),
+                message,
+            },
+            s => ApiError::Rejected {
+                status: s.as_u16(),
+                message,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Unauthorized => write!(f, "API token rejected"),
+            ApiError::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(f, "Rate limited, retry in {}s", retry_after.as_secs()),
+            ApiError::RateLimited { retry_after: None } => write!(f, "Rate limited"),
+            ApiError::NotFound => write!(f, "Not found"),
+            ApiError::Rejected { status, message } => {
+                write!(f, "Request rejected ({}): {}", status, message)
+            }
+            ApiError::Server { status, message } => {
+                write!(f, "Todoist server error ({}): {}", status, message)
+            }
+            ApiError::Network(e) => write!(f, "Network error: {}", e),
+            ApiError::Decode(message) => write!(f, "Unexpected response: {}", message),
+            ApiError::Command(message) => write!(f, "Sync command failed: {}", message),
+            ApiError::Storage(message) => write!(f, "Local storage error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            ApiError::Decode(error.to_string())
+        } else {
+            ApiError::Network(error)
+        }
+    }
+}
+
+/// One page of a listing endpoint. Most endpoints call the list `results`;
+/// the completed-tasks ones call it `items`.
 #[derive(Debug, Clone, Deserialize)]
-struct TasksResponse {
-    results: Vec<Task>,
+struct Page<T> {
+    #[serde(alias = "items")]
+    results: Vec<T>,
+    #[serde(default)]
+    next_cursor: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
 pub struct Task {
     pub id: String,
+    #[serde(default)]
+    pub project_id: String,
+    #[serde(default)]
+    pub section_id: Option<String>,
+    /// The task this one is a subtask of.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Position among the parent's subtasks.
+    #[serde(default)]
+    pub child_order: i64,
     pub content: String,
     #[serde(default)]
     pub description: String,
+    /// Names of the task's labels.
     #[serde(default)]
+    pub labels: Vec<String>,
+    /// Called `checked` by the Sync API.
+    #[serde(default, alias = "checked")]
     pub is_completed: bool,
     pub due: Option<Due>,
     pub priority: u8,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Due {
     pub date: String,
     pub is_recurring: bool,
@@ -35,67 +169,432 @@ pub struct Due {
     pub timezone: Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub color: String,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub child_order: i64,
+    #[serde(default)]
+    pub is_inbox_project: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Label {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub color: String,
+    #[serde(default)]
+    pub order: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Section {
+    pub id: String,
+    pub project_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub section_order: i64,
+}
+
+/// A resource as returned by the Sync API, which also reports deletions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncResource<T> {
+    #[serde(flatten)]
+    pub resource: T,
+    #[serde(default)]
+    pub is_deleted: bool,
+    /// Archived projects are gone from the user's point of view.
+    #[serde(default)]
+    pub is_archived: bool,
+    /// When a completed item was completed, as an RFC 3339 timestamp.
+    #[serde(default)]
+    pub completed_at: Option<String>,
+}
+
+/// A write to send through the Sync API. Commands in one request run in
+/// order, and later ones may refer to an earlier `temp_id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncCommand {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Todoist deduplicates commands on this, so retries are safe.
+    pub uuid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temp_id: Option<String>,
+    pub args: serde_json::Value,
+}
+
+/// The answer to a Sync API request: what changed since the `sync_token`
+/// that was sent, and how each command fared.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SyncResponse {
+    #[serde(default)]
+    pub sync_token: Option<String>,
+    /// Whether this is everything rather than a delta, e.g. because the
+    /// token was `*` or too old.
+    #[serde(default)]
+    pub full_sync: bool,
+    #[serde(default)]
+    pub items: Vec<SyncResource<Task>>,
+    #[serde(default)]
+    pub projects: Vec<SyncResource<Project>>,
+    #[serde(default)]
+    pub labels: Vec<SyncResource<Label>>,
+    /// `"ok"` or an error object, keyed by command UUID.
+    #[serde(default)]
+    pub sync_status: HashMap<String, serde_json::Value>,
+    /// Real IDs of resources created with a `temp_id`.
+    #[serde(default)]
+    pub temp_id_mapping: HashMap<String, String>,
+}
+
+/// Fields to change on an existing task. `None` fields are left untouched.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct TaskUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_string: Option<String>,
+    /// A "YYYY-MM-DD" date, or "YYYY-MM-DDTHH:MM:SS" (ending in `Z` for a
+    /// fixed timezone) for a time of day, as in the Sync API's `due.date`.
+    /// Alongside `due_string`, the string is kept as it is rather than worked
+    /// out again, so a recurring task moves to the date and goes on recurring.
+    #[serde(skip_serializing_if = "Option::is_none", alias = "due_datetime")]
+    pub due_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+}
+
+/// How hard to try before giving up on a request.
+///
+/// Network failures, server errors and rate limits are retried with jittered
+/// exponential backoff, or after the `Retry-After` delay Todoist asks for.
+/// Every mutating call carries an idempotency key, so retrying is safe.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each one after it.
+    pub base_delay: Duration,
+    /// Longest wait before a retry. A longer `Retry-After` fails the request
+    /// instead, leaving it to the caller's own retry schedule.
+    pub max_delay: Duration,
+    /// How long a single attempt may take, response body included.
+    pub request_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt` (from 0) after
+    /// `error`, or `None` if the request shouldn't be retried.
+    fn delay(&self, error: &ApiError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        match error {
+            ApiError::RateLimited {
+                retry_after: Some(retry_after),
+            } => (*retry_after <= self.max_delay).then_some(*retry_after),
+            ApiError::RateLimited { retry_after: None }
+            | ApiError::Network(_)
+            | ApiError::Server { .. } => Some(self.backoff(attempt)),
+            _ => None,
+        }
+    }
+
+    /// Exponential backoff with "equal jitter": somewhere between half and
+    /// all of `base_delay * 2^attempt`, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let half = ceiling / 2;
+        let jitter_ms = RandomState::new().hash_one(attempt) % (half.as_millis() as u64 + 1);
+        half + Duration::from_millis(jitter_ms)
+    }
+}
+
 #[derive(Clone)]
 pub struct TodoistClient {
-    api_token: String,
+    /// Shared so a replacement token reaches every clone of the client.
+    api_token: Arc<RwLock<String>>,
     base_url: String,
     client: reqwest::Client,
+    /// How many results to ask for per page of a listing endpoint.
+    page_limit: usize,
+    /// Whether to refresh and push changes through the incremental Sync API
+    /// rather than the REST endpoints.
+    incremental_sync: bool,
+    retry_policy: RetryPolicy,
+    /// When the request currently backing off will be retried, if any.
+    retrying_at: Arc<RwLock<Option<Instant>>>,
 }
 
 impl TodoistClient {
     pub fn new(api_token: String) -> Self {
         Self {
-            api_token,
-            base_url: "https://api.todoist.com/api/v1".to_string(),
+            api_token: Arc::new(RwLock::new(api_token)),
+            base_url: DEFAULT_BASE_URL.to_string(),
             client: reqwest::Client::new(),
+            page_limit: 50,
+            incremental_sync: false,
+            retry_policy: RetryPolicy::default(),
+            retrying_at: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Talk to the API at `base_url` (e.g. a local mock server) instead of
+    /// Todoist's.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    fn api_token(&self) -> String {
+        match self.api_token.read() {
+            Ok(token) => token.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Refresh and push changes through the Sync API instead of REST.
+    pub fn with_incremental_sync(mut self, enabled: bool) -> Self {
+        self.incremental_sync = enabled;
+        self
+    }
+
+    /// Retry failed requests according to `policy`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    fn set_retrying_at(&self, at: Option<Instant>) {
+        match self.retrying_at.write() {
+            Ok(mut retrying_at) => *retrying_at = at,
+            Err(poisoned) => *poisoned.into_inner() = at,
         }
     }
 
-    /// Fetch today's tasks from the Todoist API
-    pub async fn get_todays_tasks(
+    /// Ask for `limit` results per page (clamped to 1–`MAX_PAGE_LIMIT`).
+    pub fn with_page_limit(mut self, limit: usize) -> Self {
+        self.page_limit = limit.clamp(1, MAX_PAGE_LIMIT);
+        self
+    }
+
+    /// Stream the active tasks matching a Todoist filter expression a page at
+    /// a time
+    fn filter_task_pages(
         &self,
-    ) -> Result<Vec<Task>, Box<dyn std::error::Error + Send + Sync>> {
-        let url = format!("{}/tasks", self.base_url);
+        query: &str,
+    ) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_ {
+        self.pages("tasks/filter", vec![("query", query.to_string())])
+    }
 
+    /// Stream every page of a listing endpoint, following `next_cursor` until
+    /// the server says there are no more.
+    fn pages<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: Vec<(&'static str, String)>,
+    ) -> impl Stream<Item = Result<Vec<T>, ApiError>> + Send + '_ {
+        let url = format!("{}/{}", self.base_url, path);
+        // `None` once the last page is in; `Some(None)` before the first one.
+        stream::try_unfold(Some(None), move |cursor: Option<Option<String>>| {
+            let url = url.clone();
+            let query = query.clone();
+            async move {
+                let Some(cursor) = cursor else {
+                    return Ok(None);
+                };
+                let page: Page<T> = self.fetch_page(&url, &query, cursor.as_deref()).await?;
+                log::debug!(
+                    "Retrieved {} results from {} (more: {})",
+                    page.results.len(),
+                    url,
+                    page.next_cursor.is_some()
+                );
+                Ok(Some((page.results, page.next_cursor.map(Some))))
+            }
+        })
+    }
+
+    /// Fetch a single page of a listing endpoint
+    async fn fetch_page<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&'static str, String)],
+        cursor: Option<&str>,
+    ) -> Result<Page<T>, ApiError> {
         // Log the URL and query parameters
-        log::debug!("Sending GET request to {} with query filter=today", url);
+        log::debug!(
+            "Sending GET request to {} with query {:?} (cursor {:?})",
+            url,
+            query,
+            cursor
+        );
 
-        let response = self
+        let mut request = self
             .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .query(&[("filter", "today")])
-            .send()
+            .get(url)
+            .bearer_auth(self.api_token())
+            .query(query)
+            .query(&[("limit", self.page_limit)]);
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let response = self.send_expecting_success(request).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Send a body-less POST request, treating any 2xx status as success
+    /// Run a single Sync API command, for a write the REST endpoints can't
+    /// make. The request ID doubles as the command UUID, which the sync
+    /// endpoint deduplicates on.
+    async fn run_sync_command(
+        &self,
+        kind: &str,
+        args: serde_json::Value,
+        request_id: &str,
+    ) -> Result<(), ApiError> {
+        let url = format!("{}/sync", self.base_url);
+        let body = serde_json::json!({
+            "commands": [{ "type": kind, "uuid": request_id, "args": args }]
+        });
+        let response: SyncResponse = self
+            .post_json(&url, &body, request_id)
+            .await?
+            .json()
             .await?;
+        match response.sync_status.get(request_id) {
+            Some(status) if status == "ok" => Ok(()),
+            Some(error) => Err(ApiError::Command(error.to_string())),
+            None => Err(ApiError::Command("command was not run".to_string())),
+        }
+    }
+
+    async fn post_empty(&self, url: &str, request_id: &str) -> Result<(), ApiError> {
+        log::debug!(
+            "Sending POST request to {} (request id {})",
+            url,
+            request_id
+        );
+
+        let request = self
+            .client
+            .post(url)
+            .bearer_auth(self.api_token())
+            .header("X-Request-Id", request_id);
+        self.send_expecting_success(request).await?;
+        Ok(())
+    }
+
+    /// Send a POST request with a JSON body, treating any 2xx status as success
+    async fn post_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+        request_id: &str,
+    ) -> Result<reqwest::Response, ApiError> {
+        log::debug!(
+            "Sending POST request with JSON body to {} (request id {})",
+            url,
+            request_id
+        );
+
+        let request = self
+            .client
+            .post(url)
+            .bearer_auth(self.api_token())
+            .header("X-Request-Id", request_id)
+            .json(body);
+        self.send_expecting_success(request).await
+    }
+
+    /// Send a request, retrying transient failures according to the retry
+    /// policy, and treat any 2xx status as success
+    async fn send_expecting_success(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, ApiError> {
+        let request = request.timeout(self.retry_policy.request_timeout);
+        let mut attempt = 0;
+        loop {
+            // Only streamed bodies can't be cloned, and none are sent.
+            let Some(this_attempt) = request.try_clone() else {
+                return Self::send_once(request).await;
+            };
+            let error = match Self::send_once(this_attempt).await {
+                Err(error) => error,
+                result => return result,
+            };
+            let Some(delay) = self.retry_policy.delay(&error, attempt) else {
+                return Err(error);
+            };
+            log::warn!("{}; retrying in {:?}", error, delay);
+            let retry_at = Instant::now() + delay;
+            self.set_retrying_at(Some(retry_at));
+            tokio::time::sleep(delay).await;
+            // Another request may have started backing off in the meantime.
+            if self.retrying_at() == Some(retry_at) {
+                self.set_retrying_at(None);
+            }
+            attempt += 1;
+        }
+    }
+
+    async fn send_once(request: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let response = request.send().await?;
 
-        // Store status code before consuming the response
         let status = response.status();
         log::debug!("Response HTTP status: {}", status);
-
         if !status.is_success() {
-            let error_text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "No body".to_string());
+            // Only the delay-seconds form of Retry-After is understood.
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            let error_text = response.text().await.unwrap_or_default();
             log::error!("Error response body: {}", error_text);
-            return Err(format!(
-                "API request failed with status: {} - {}",
-                status, error_text
-            )
-            .into());
+            return Err(ApiError::from_status(status, retry_after, error_text));
         }
 
-        let tasks_resp: TasksResponse = response.json().await?;
-        log::debug!("Retrieved {} tasks", tasks_resp.results.len());
-        Ok(tasks_resp.results)
+        Ok(response)
+    }
+}
+
+impl Backend for TodoistClient {
+    /// Stream today's tasks from the Todoist API a page at a time
+    fn todays_task_pages(&self) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_ {
+        self.filter_task_pages("today")
     }
 
-    /// Fetch today's completed tasks from the Todoist API
-    pub async fn get_todays_completed_tasks(
+    /// Stream the tasks completed today from the Todoist API a page at a time
+    fn todays_completed_task_pages(
         &self,
-    ) -> Result<Vec<Task>, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_ {
         // Use the completed-by-completion-date endpoint.
-        let url = format!("{}/tasks/completed/by_completion_date", self.base_url);
         let today = Local::now().date_naive();
         let start = today.and_hms_opt(0, 0, 0).unwrap();
         let end = (today + chrono::Duration::days(1))
@@ -105,32 +604,245 @@ impl TodoistClient {
         let until: String = Local.from_local_datetime(&end).unwrap().to_rfc3339();
 
         log::debug!("Fetching completed tasks from {} to {}", since, until);
+        self.pages(
+            "tasks/completed/by_completion_date",
+            vec![("since", since), ("until", until)],
+        )
+    }
+
+    /// Fetch the active tasks matching a Todoist filter expression, e.g.
+    /// `p1 & #Work | overdue`
+    async fn get_tasks_by_filter(&self, query: &str) -> Result<Vec<Task>, ApiError> {
+        self.filter_task_pages(query).try_concat().await
+    }
+
+    /// Fetch the active tasks in a project from the Todoist API
+    async fn get_project_tasks(&self, project_id: &str) -> Result<Vec<Task>, ApiError> {
+        self.pages("tasks", vec![("project_id", project_id.to_string())])
+            .try_concat()
+            .await
+    }
 
-        let response = self
+    /// Fetch the active tasks carrying a label from the Todoist API
+    async fn get_label_tasks(&self, label: &str) -> Result<Vec<Task>, ApiError> {
+        self.pages("tasks", vec![("label", label.to_string())])
+            .try_concat()
+            .await
+    }
+
+    /// Fetch all of the user's personal labels from the Todoist API
+    async fn get_labels(&self) -> Result<Vec<Label>, ApiError> {
+        self.pages("labels", Vec::new()).try_concat().await
+    }
+
+    /// Fetch all of the user's projects from the Todoist API
+    async fn get_projects(&self) -> Result<Vec<Project>, ApiError> {
+        self.pages("projects", Vec::new()).try_concat().await
+    }
+
+    /// Fetch the sections of a project from the Todoist API
+    async fn get_sections(&self, project_id: &str) -> Result<Vec<Section>, ApiError> {
+        self.pages("sections", vec![("project_id", project_id.to_string())])
+            .try_concat()
+            .await
+    }
+
+    // Mutating calls take a `request_id` which Todoist uses to deduplicate
+    // retries of the same request (sent as `X-Request-Id`).
+
+    /// Mark a task as completed on the Todoist API
+    async fn close_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        let url = format!("{}/tasks/{}/close", self.base_url, task_id);
+        self.post_empty(&url, request_id).await
+    }
+
+    /// Mark a completed task as active again on the Todoist API
+    async fn reopen_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        let url = format!("{}/tasks/{}/reopen", self.base_url, task_id);
+        self.post_empty(&url, request_id).await
+    }
+
+    /// Update a task's content, description, due date and/or priority
+    async fn update_task(
+        &self,
+        task_id: &str,
+        update: &TaskUpdate,
+        request_id: &str,
+    ) -> Result<(), ApiError> {
+        if let (Some(date), Some(due_string)) = (&update.due_date, &update.due_string) {
+            // REST takes one due field at a time, and a date on its own ends
+            // a recurrence. The Sync API keeps a string sent with the date,
+            // so the task moves and goes on recurring.
+            let mut args = serde_json::json!(TaskUpdate {
+                due_string: None,
+                due_date: None,
+                ..update.clone()
+            });
+            args["id"] = serde_json::Value::from(task_id);
+            args["due"] = serde_json::json!({ "date": date, "string": due_string });
+            return self.run_sync_command("item_update", args, request_id).await;
+        }
+
+        let url = format!("{}/tasks/{}", self.base_url, task_id);
+        let mut body = serde_json::json!(update);
+        if let (Some(date), Some(fields)) = (&update.due_date, body.as_object_mut()) {
+            if date.len() > 10 {
+                fields.insert("due_datetime".to_string(), date.clone().into());
+                fields.remove("due_date");
+            }
+        }
+        self.post_json(&url, &body, request_id).await?;
+        Ok(())
+    }
+
+    /// Persist the order of tasks in the Today view.
+    ///
+    /// The REST endpoints have no reorder call, so this goes through the sync
+    /// endpoint with a single `item_update_day_orders` command.
+    async fn reorder_tasks(&self, task_ids: &[String], request_id: &str) -> Result<(), ApiError> {
+        let ids_to_orders: serde_json::Map<String, serde_json::Value> = task_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.clone(), serde_json::Value::from(i + 1)))
+            .collect();
+        let args = serde_json::json!({ "ids_to_orders": ids_to_orders });
+        self.run_sync_command("item_update_day_orders", args, request_id)
+            .await
+    }
+
+    /// Create a task from a line of Todoist quick-add syntax, e.g.
+    /// `Call mum tomorrow 5pm #Family @phone p2`. The server parses the
+    /// project, labels, priority and due date out of the text.
+    async fn quick_add_task(&self, text: &str, request_id: &str) -> Result<Task, ApiError> {
+        let url = format!("{}/tasks/quick", self.base_url);
+        let body = serde_json::json!({ "text": text });
+        let response = self.post_json(&url, &body, request_id).await?;
+        let task: Task = response.json().await?;
+        log::debug!("Quick-added task {}", task.id);
+        Ok(task)
+    }
+
+    /// Create a task with the same content, description, priority and due
+    /// date as `task`, e.g. to bring back a deleted one. Returns the new task.
+    async fn create_task(&self, task: &Task, request_id: &str) -> Result<Task, ApiError> {
+        let url = format!("{}/tasks", self.base_url);
+        let mut body = serde_json::json!({
+            "content": task.content,
+            "description": task.description,
+            "priority": task.priority,
+            "labels": task.labels,
+        });
+        if !task.project_id.is_empty() {
+            body["project_id"] = serde_json::Value::from(task.project_id.clone());
+        }
+        if let Some(section_id) = &task.section_id {
+            body["section_id"] = serde_json::Value::from(section_id.clone());
+        }
+        if let Some(parent_id) = &task.parent_id {
+            body["parent_id"] = serde_json::Value::from(parent_id.clone());
+        }
+        if let Some(due) = &task.due {
+            // REST takes one due field at a time: the string for a
+            // recurrence, otherwise the exact date it was due.
+            match &due.datetime {
+                _ if due.is_recurring => {
+                    body["due_string"] = serde_json::Value::from(due.string.clone())
+                }
+                Some(datetime) => body["due_datetime"] = serde_json::Value::from(datetime.clone()),
+                None => body["due_date"] = serde_json::Value::from(due.date.clone()),
+            }
+        }
+        let response = self.post_json(&url, &body, request_id).await?;
+        let mut created: Task = response.json().await?;
+        log::debug!("Created task {}", created.id);
+        // A task can't be created completed, so close it straight after.
+        if task.is_completed {
+            self.close_task(&created.id, &format!("{}-close", request_id))
+                .await?;
+            created.is_completed = true;
+        }
+        Ok(created)
+    }
+
+    /// Permanently delete a task
+    async fn delete_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        let url = format!("{}/tasks/{}", self.base_url, task_id);
+        log::debug!(
+            "Sending DELETE request to {} (request id {})",
+            url,
+            request_id
+        );
+
+        let request = self
             .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.api_token))
-            .query(&[("since", since.as_str()), ("until", until.as_str())])
-            .send()
-            .await?;
+            .delete(&url)
+            .bearer_auth(self.api_token())
+            .header("X-Request-Id", request_id);
+        self.send_expecting_success(request).await?;
+        Ok(())
+    }
 
-        let status = response.status();
-        log::debug!("Completed tasks response status: {}", status);
-        if !status.is_success() {
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!(
-                "Error fetching completed tasks: {} - {}",
-                status, error_text
-            )
-            .into());
+    fn store_name(&self) -> &'static str {
+        "todoist"
+    }
+
+    fn uses_incremental_sync(&self) -> bool {
+        self.incremental_sync
+    }
+
+    /// Read everything that changed since `sync_token` (`*` for everything)
+    /// and run `commands`, through the Sync API.
+    ///
+    /// With `sync_token` set to `None` only the commands are run and no
+    /// resources are read back.
+    async fn sync(
+        &self,
+        sync_token: Option<&str>,
+        commands: &[SyncCommand],
+    ) -> Result<SyncResponse, ApiError> {
+        let url = format!("{}/sync", self.base_url);
+        let mut body = serde_json::json!({ "commands": commands });
+        if let Some(sync_token) = sync_token {
+            body["sync_token"] = serde_json::Value::from(sync_token);
+            body["resource_types"] = serde_json::json!(["items", "projects", "labels"]);
+        }
+        log::debug!(
+            "Sending sync request (token {:?}, {} command(s))",
+            sync_token,
+            commands.len()
+        );
+
+        let request = self
+            .client
+            .post(&url)
+            .bearer_auth(self.api_token())
+            .json(&body);
+        let response = self.send_expecting_success(request).await?;
+        let sync_resp: SyncResponse = response.json().await?;
+        log::debug!(
+            "Sync returned {} items, {} projects, {} labels (full sync: {})",
+            sync_resp.items.len(),
+            sync_resp.projects.len(),
+            sync_resp.labels.len(),
+            sync_resp.full_sync
+        );
+        Ok(sync_resp)
+    }
+
+    /// Use a new API token from now on, e.g. after the old one was rejected.
+    fn set_api_token(&self, api_token: String) {
+        match self.api_token.write() {
+            Ok(mut token) => *token = api_token,
+            Err(poisoned) => *poisoned.into_inner() = api_token,
         }
+    }
 
-        #[derive(Debug, Deserialize)]
-        struct CompletedTasksResponse {
-            items: Vec<Task>,
+    /// When a request that failed will next be retried, while one is
+    /// waiting to.
+    fn retrying_at(&self) -> Option<Instant> {
+        match self.retrying_at.read() {
+            Ok(retrying_at) => *retrying_at,
+            Err(poisoned) => *poisoned.into_inner(),
         }
-        let comp_resp: CompletedTasksResponse = response.json().await?;
-        log::debug!("Fetched {} completed tasks", comp_resp.items.len());
-        Ok(comp_resp.items)
     }
 }
@@ -0,0 +1,138 @@
+//! Task storage backends
+//!
+//! Handles:
+//! - The operations the sync and UI code need from wherever tasks live
+//! - Optional extras only the Todoist API has (incremental sync, API tokens,
+//!   retry backoff), with defaults for backends that don't
+
+use crate::api::{ApiError, Label, Project, Section, SyncCommand, SyncResponse, Task, TaskUpdate};
+use futures::{Future, Stream};
+use std::time::Instant;
+
+/// Where tasks are fetched from and changes are written to.
+///
+/// Mutating calls take a `request_id` identifying the change, so a backend
+/// can recognise a retry of a request it already handled.
+pub trait Backend: Send + Sync + 'static {
+    /// Stream today's active tasks, overdue ones included, a page at a time
+    fn todays_task_pages(&self) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_;
+
+    /// Stream the tasks completed today a page at a time
+    fn todays_completed_task_pages(
+        &self,
+    ) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_;
+
+    /// Fetch the active tasks matching a Todoist filter expression
+    fn get_tasks_by_filter(
+        &self,
+        query: &str,
+    ) -> impl Future<Output = Result<Vec<Task>, ApiError>> + Send;
+
+    /// Fetch the active tasks in a project
+    fn get_project_tasks(
+        &self,
+        project_id: &str,
+    ) -> impl Future<Output = Result<Vec<Task>, ApiError>> + Send;
+
+    /// Fetch the active tasks carrying a label
+    fn get_label_tasks(
+        &self,
+        label: &str,
+    ) -> impl Future<Output = Result<Vec<Task>, ApiError>> + Send;
+
+    /// Fetch all of the user's labels
+    fn get_labels(&self) -> impl Future<Output = Result<Vec<Label>, ApiError>> + Send;
+
+    /// Fetch all of the user's projects
+    fn get_projects(&self) -> impl Future<Output = Result<Vec<Project>, ApiError>> + Send;
+
+    /// Fetch the sections of a project
+    fn get_sections(
+        &self,
+        project_id: &str,
+    ) -> impl Future<Output = Result<Vec<Section>, ApiError>> + Send;
+
+    /// Mark a task as completed
+    fn close_task(
+        &self,
+        task_id: &str,
+        request_id: &str,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send;
+
+    /// Mark a completed task as active again
+    fn reopen_task(
+        &self,
+        task_id: &str,
+        request_id: &str,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send;
+
+    /// Update a task's content, description, due date and/or priority
+    fn update_task(
+        &self,
+        task_id: &str,
+        update: &TaskUpdate,
+        request_id: &str,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send;
+
+    /// Persist the order of tasks in the Today view
+    fn reorder_tasks(
+        &self,
+        task_ids: &[String],
+        request_id: &str,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send;
+
+    /// Create a task from a line of Todoist quick-add syntax, returning it
+    fn quick_add_task(
+        &self,
+        text: &str,
+        request_id: &str,
+    ) -> impl Future<Output = Result<Task, ApiError>> + Send;
+
+    /// Create a copy of `task` under a new ID, returning it
+    fn create_task(
+        &self,
+        task: &Task,
+        request_id: &str,
+    ) -> impl Future<Output = Result<Task, ApiError>> + Send;
+
+    /// Permanently delete a task
+    fn delete_task(
+        &self,
+        task_id: &str,
+        request_id: &str,
+    ) -> impl Future<Output = Result<(), ApiError>> + Send;
+
+    /// Names the store that caches this backend's tasks and queues its
+    /// changes. Backends never share a store, so changes queued for one are
+    /// never replayed against another.
+    fn store_name(&self) -> &'static str;
+
+    /// Whether to refresh and push changes through `sync` rather than the
+    /// calls above.
+    fn uses_incremental_sync(&self) -> bool {
+        false
+    }
+
+    /// Read what changed since `sync_token` and run `commands`, Sync API
+    /// style. Only called when `uses_incremental_sync` is true.
+    fn sync(
+        &self,
+        _sync_token: Option<&str>,
+        _commands: &[SyncCommand],
+    ) -> impl Future<Output = Result<SyncResponse, ApiError>> + Send {
+        async {
+            Err(ApiError::Command(
+                "this backend has no incremental sync".to_string(),
+            ))
+        }
+    }
+
+    /// Use a new API token from now on, if the backend has one.
+    fn set_api_token(&self, _api_token: String) {}
+
+    /// When a request that failed will next be retried, while one is
+    /// waiting to.
+    fn retrying_at(&self) -> Option<Instant> {
+        None
+    }
+}
@@ -0,0 +1,216 @@
+//! Conflicts between queued edits and changes made on the server
+//!
+//! Handles:
+//! - Working out which fields of a task both a queued edit and the server
+//!   changed since the edit was made, and to different values
+//! - Per-field choices between the local and the server value
+//! - Narrowing queued edits down to the fields whose local value is kept
+
+use crate::api::{Task, TaskUpdate};
+use crate::state::NO_DUE_DATE;
+
+/// The fields of a task an edit can change, in edit form order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskField {
+    Content,
+    Description,
+    Due,
+    Priority,
+}
+
+impl TaskField {
+    pub const ALL: [TaskField; 4] = [
+        TaskField::Content,
+        TaskField::Description,
+        TaskField::Due,
+        TaskField::Priority,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TaskField::Content => "Content",
+            TaskField::Description => "Description",
+            TaskField::Due => "Due",
+            TaskField::Priority => "Priority",
+        }
+    }
+
+    /// The field's value in `task`, as shown to the user.
+    fn value(self, task: &Task) -> String {
+        match self {
+            TaskField::Content => task.content.clone(),
+            TaskField::Description => task.description.clone(),
+            TaskField::Due => task
+                .due
+                .as_ref()
+                .map_or_else(String::new, |d| d.string.clone()),
+            TaskField::Priority => format!("p{}", 5 - task.priority.clamp(1, 4)),
+        }
+    }
+
+    /// Whether the field differs between two copies of a task.
+    fn differs(self, a: &Task, b: &Task) -> bool {
+        match self {
+            TaskField::Content => a.content != b.content,
+            TaskField::Description => a.description != b.description,
+            TaskField::Due => a.due != b.due,
+            TaskField::Priority => a.priority != b.priority,
+        }
+    }
+
+    /// The value `update` sets the field to, as shown to the user, if it
+    /// sets it at all.
+    fn update_value(self, update: &TaskUpdate) -> Option<String> {
+        match self {
+            TaskField::Content => update.content.clone(),
+            TaskField::Description => update.description.clone(),
+            TaskField::Due => match (&update.due_string, update.due_date.as_ref()) {
+                (Some(due_string), None) if due_string.eq_ignore_ascii_case(NO_DUE_DATE) => {
+                    Some(String::new())
+                }
+                (Some(due_string), None) => Some(due_string.clone()),
+                (Some(due_string), Some(date)) => Some(format!("{} ({})", due_string, date)),
+                (None, due_date) => due_date.cloned(),
+            },
+            TaskField::Priority => update.priority.map(|p| format!("p{}", 5 - p.clamp(1, 4))),
+        }
+    }
+
+    /// Whether `task` already has the value `update` sets the field to.
+    fn matches_update(self, task: &Task, update: &TaskUpdate) -> bool {
+        match self {
+            TaskField::Due => match (&update.due_string, update.due_date.as_ref(), &task.due) {
+                (Some(due_string), None, None) => due_string.eq_ignore_ascii_case(NO_DUE_DATE),
+                (Some(due_string), None, Some(due)) => due.string.eq_ignore_ascii_case(due_string),
+                (_, Some(date), Some(due)) => due.datetime.as_ref().unwrap_or(&due.date) == date,
+                _ => false,
+            },
+            _ => self.update_value(update) == Some(self.value(task)),
+        }
+    }
+
+    /// Stop `update` from touching the field.
+    pub fn clear(self, update: &mut TaskUpdate) {
+        match self {
+            TaskField::Content => update.content = None,
+            TaskField::Description => update.description = None,
+            TaskField::Due => {
+                update.due_string = None;
+                update.due_date = None;
+            }
+            TaskField::Priority => update.priority = None,
+        }
+    }
+
+    /// Give `task` the field's value from `from`.
+    pub fn copy(self, from: &Task, task: &mut Task) {
+        match self {
+            TaskField::Content => task.content = from.content.clone(),
+            TaskField::Description => task.description = from.description.clone(),
+            TaskField::Due => task.due = from.due.clone(),
+            TaskField::Priority => task.priority = from.priority,
+        }
+    }
+}
+
+/// One field both sides changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldConflict {
+    pub field: TaskField,
+    /// The value before either change.
+    pub base: String,
+    pub mine: String,
+    pub theirs: String,
+    /// Whether resolving keeps the local value rather than the server's.
+    pub keep_mine: bool,
+}
+
+/// A task whose queued edits and server copy changed the same fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub task_id: String,
+    /// The task as the server has it now.
+    pub theirs: Task,
+    pub fields: Vec<FieldConflict>,
+    /// Index into `fields` of the row focused in the resolution dialog.
+    pub focused: usize,
+}
+
+impl Conflict {
+    /// Compare the server's copy of a task with the `base` copy the queued
+    /// edits were made against. `mine` is what those edits set, combined.
+    /// Returns `None` unless some field was changed on both sides to
+    /// different values.
+    pub fn detect(base: &Task, theirs: &Task, mine: &TaskUpdate) -> Option<Conflict> {
+        let fields: Vec<FieldConflict> = TaskField::ALL
+            .into_iter()
+            .filter_map(|field| {
+                let mine_value = field.update_value(mine)?;
+                if !field.differs(base, theirs) || field.matches_update(theirs, mine) {
+                    return None;
+                }
+                Some(FieldConflict {
+                    field,
+                    base: field.value(base),
+                    mine: mine_value,
+                    theirs: field.value(theirs),
+                    keep_mine: true,
+                })
+            })
+            .collect();
+        if fields.is_empty() {
+            return None;
+        }
+        Some(Conflict {
+            task_id: theirs.id.clone(),
+            theirs: theirs.clone(),
+            fields,
+            focused: 0,
+        })
+    }
+
+    pub fn focus_next(&mut self) {
+        self.focused = (self.focused + 1) % self.fields.len();
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.focused = (self.focused + self.fields.len() - 1) % self.fields.len();
+    }
+
+    /// Switch the focused field between the local and the server value.
+    pub fn toggle_focused(&mut self) {
+        if let Some(field) = self.fields.get_mut(self.focused) {
+            field.keep_mine = !field.keep_mine;
+        }
+    }
+
+    /// The fields resolving gives the server's value.
+    pub fn fields_kept_theirs(&self) -> Vec<TaskField> {
+        self.fields
+            .iter()
+            .filter(|f| !f.keep_mine)
+            .map(|f| f.field)
+            .collect()
+    }
+}
+
+/// Combine a task's queued updates in order; later ones win per field.
+pub fn combine_updates<'a>(updates: impl IntoIterator<Item = &'a TaskUpdate>) -> TaskUpdate {
+    let mut combined = TaskUpdate::default();
+    for update in updates {
+        if update.content.is_some() {
+            combined.content = update.content.clone();
+        }
+        if update.description.is_some() {
+            combined.description = update.description.clone();
+        }
+        if update.due_string.is_some() || update.due_date.is_some() {
+            combined.due_string = update.due_string.clone();
+            combined.due_date = update.due_date.clone();
+        }
+        if update.priority.is_some() {
+            combined.priority = update.priority;
+        }
+    }
+    combined
+}
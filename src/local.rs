@@ -0,0 +1,396 @@
+//! Local-only task storage
+//!
+//! Handles:
+//! - Keeping tasks, projects, labels and sections in a JSON file, with no
+//!   Todoist account needed
+//! - Answering the same fetches as the Todoist API, including a small subset
+//!   of its filter syntax
+//! - Applying changes and writing the file back atomically after each one,
+//!   skipping requests it has applied already
+
+use crate::api::{ApiError, Due, Label, Project, Section, Task, TaskUpdate};
+use crate::backend::Backend;
+use crate::state::{due_date, quick_add_preview, update_task_fields, NO_DUE_DATE};
+use chrono::{DateTime, Local, NaiveDate};
+use futures::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const LOCAL_FILE_NAME: &str = "local_tasks.json";
+
+/// How many applied request IDs the file remembers. Replays only happen for
+/// the few changes in flight when the app stopped.
+const MAX_APPLIED_REQUESTS: usize = 1000;
+
+/// Tasks kept in a JSON file on this machine.
+pub struct LocalBackend {
+    path: PathBuf,
+    data: Mutex<LocalData>,
+}
+
+/// Everything in the task file. Projects, labels and sections can be added
+/// by editing it by hand.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct LocalData {
+    #[serde(default)]
+    tasks: Vec<LocalTask>,
+    #[serde(default)]
+    projects: Vec<Project>,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    /// IDs of the latest requests applied, oldest first.
+    #[serde(default)]
+    applied_requests: VecDeque<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct LocalTask {
+    #[serde(flatten)]
+    task: Task,
+    /// When the task was completed, to tell which were completed today.
+    #[serde(default)]
+    completed_at: Option<DateTime<Local>>,
+}
+
+impl LocalBackend {
+    /// The task file under the XDG data dir.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("tuidoist").join(LOCAL_FILE_NAME))
+    }
+
+    /// Open the task file at `path`, starting empty if there isn't one yet.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let data = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LocalData::default(),
+            Err(e) => return Err(e),
+        };
+        log::debug!("Opened local task file {}", path.display());
+        Ok(Self {
+            path,
+            data: Mutex::new(data),
+        })
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&LocalData) -> T) -> T {
+        match self.data.lock() {
+            Ok(data) => f(&data),
+            Err(poisoned) => f(&poisoned.into_inner()),
+        }
+    }
+
+    /// Apply a change and write the file, once per request. Nothing changes
+    /// unless both work.
+    fn write(
+        &self,
+        request_id: &str,
+        f: impl FnOnce(&mut LocalData) -> Result<(), ApiError>,
+    ) -> Result<(), ApiError> {
+        self.apply_once(request_id, f).map(|_| ())
+    }
+
+    /// Apply a change and write the file, unless the request was applied
+    /// before (e.g. replayed after the app stopped between writing the file
+    /// and recording the change as done); `None` is returned then.
+    fn apply_once<T>(
+        &self,
+        request_id: &str,
+        f: impl FnOnce(&mut LocalData) -> Result<T, ApiError>,
+    ) -> Result<Option<T>, ApiError> {
+        let mut data = match self.data.lock() {
+            Ok(data) => data,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if !request_id.is_empty() && data.applied_requests.iter().any(|id| id == request_id) {
+            log::debug!("Skipping request {} applied already", request_id);
+            return Ok(None);
+        }
+        let mut updated = data.clone();
+        let result = f(&mut updated)?;
+        if !request_id.is_empty() {
+            updated.applied_requests.push_back(request_id.to_string());
+            if updated.applied_requests.len() > MAX_APPLIED_REQUESTS {
+                updated.applied_requests.pop_front();
+            }
+        }
+        self.save(&updated)
+            .map_err(|e| ApiError::Storage(format!("{}: {}", self.path.display(), e)))?;
+        *data = updated;
+        Ok(Some(result))
+    }
+
+    /// Create a task, or return the one an earlier attempt at the same
+    /// request created.
+    fn create(
+        &self,
+        request_id: &str,
+        f: impl FnOnce(&mut LocalData) -> Result<Task, ApiError>,
+    ) -> Result<Task, ApiError> {
+        match self.apply_once(request_id, f)? {
+            Some(task) => Ok(task),
+            None => self
+                .read(|data| data.tasks.iter().find(|t| t.task.id == request_id).cloned())
+                .map(|local| local.task)
+                .ok_or(ApiError::NotFound),
+        }
+    }
+
+    fn save(&self, data: &LocalData) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(data)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Active tasks for which `keep` holds, in file order.
+    fn active_tasks(&self, keep: impl Fn(&Task) -> bool) -> Vec<Task> {
+        self.read(|data| {
+            data.tasks
+                .iter()
+                .map(|t| &t.task)
+                .filter(|t| !t.is_completed && keep(t))
+                .cloned()
+                .collect()
+        })
+    }
+}
+
+impl LocalData {
+    fn task_mut(&mut self, task_id: &str) -> Result<&mut LocalTask, ApiError> {
+        self.tasks
+            .iter_mut()
+            .find(|t| t.task.id == task_id)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Add a task, returning it. It takes the ID of the request creating it,
+    /// so a repeat of the request can find it, or a fresh one without one.
+    /// A completed task, e.g. one being restored, counts as completed now.
+    fn insert(&mut self, mut task: Task, request_id: &str) -> Task {
+        task.id = match request_id {
+            "" => Uuid::new_v4().to_string(),
+            request_id => request_id.to_string(),
+        };
+        self.tasks.push(LocalTask {
+            task: task.clone(),
+            completed_at: task.is_completed.then(Local::now),
+        });
+        task
+    }
+}
+
+/// Whether `task` matches a filter made of `&`-joined terms: `today`,
+/// `overdue`, `no date`, `p1`–`p4`, `@label` and `#project`.
+fn matches_filter(data: &LocalData, task: &Task, query: &str) -> Result<bool, ApiError> {
+    let today = Local::now().date_naive();
+    for term in query.split('&').map(str::trim) {
+        let lower = term.to_lowercase();
+        let matched = match lower.as_str() {
+            "today" => due_date(task) == Some(today),
+            "overdue" => due_date(task).is_some_and(|d| d < today),
+            NO_DUE_DATE => task.due.is_none(),
+            "p1" | "p2" | "p3" | "p4" => {
+                // Todoist's p1 (most urgent) is priority 4 in the API.
+                let level = lower[1..].parse::<u8>().unwrap_or(4);
+                task.priority == 5 - level
+            }
+            _ if term.starts_with('@') => task.labels.iter().any(|l| *l == term[1..]),
+            _ if term.starts_with('#') => data
+                .projects
+                .iter()
+                .any(|p| p.id == task.project_id && p.name.eq_ignore_ascii_case(&term[1..])),
+            _ => {
+                return Err(ApiError::Rejected {
+                    status: 400,
+                    message: format!("The local backend can't filter on {:?}", term),
+                })
+            }
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// The date a due string like `today`, `tomorrow` or `2024-05-01` stands
+/// for, if it is one of those.
+fn parse_due_string(due_string: &str) -> Option<NaiveDate> {
+    let today = Local::now().date_naive();
+    match due_string.trim().to_lowercase().as_str() {
+        "today" | "tod" => Some(today),
+        "tomorrow" | "tom" => Some(today + chrono::Duration::days(1)),
+        other => NaiveDate::parse_from_str(other, "%Y-%m-%d").ok(),
+    }
+}
+
+impl Backend for LocalBackend {
+    fn store_name(&self) -> &'static str {
+        "local"
+    }
+
+    fn todays_task_pages(&self) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_ {
+        let today = Local::now().date_naive();
+        let tasks = self.active_tasks(|task| due_date(task).is_some_and(|d| d <= today));
+        stream::iter([Ok(tasks)])
+    }
+
+    fn todays_completed_task_pages(
+        &self,
+    ) -> impl Stream<Item = Result<Vec<Task>, ApiError>> + Send + '_ {
+        let today = Local::now().date_naive();
+        let tasks = self.read(|data| {
+            data.tasks
+                .iter()
+                .filter(|t| t.task.is_completed)
+                .filter(|t| t.completed_at.is_some_and(|at| at.date_naive() == today))
+                .map(|t| t.task.clone())
+                .collect()
+        });
+        stream::iter([Ok(tasks)])
+    }
+
+    async fn get_tasks_by_filter(&self, query: &str) -> Result<Vec<Task>, ApiError> {
+        self.read(|data| {
+            let mut tasks = Vec::new();
+            for local in data.tasks.iter().filter(|t| !t.task.is_completed) {
+                if matches_filter(data, &local.task, query)? {
+                    tasks.push(local.task.clone());
+                }
+            }
+            Ok(tasks)
+        })
+    }
+
+    async fn get_project_tasks(&self, project_id: &str) -> Result<Vec<Task>, ApiError> {
+        Ok(self.active_tasks(|task| task.project_id == project_id))
+    }
+
+    async fn get_label_tasks(&self, label: &str) -> Result<Vec<Task>, ApiError> {
+        Ok(self.active_tasks(|task| task.labels.iter().any(|l| l == label)))
+    }
+
+    async fn get_labels(&self) -> Result<Vec<Label>, ApiError> {
+        Ok(self.read(|data| data.labels.clone()))
+    }
+
+    async fn get_projects(&self) -> Result<Vec<Project>, ApiError> {
+        Ok(self.read(|data| data.projects.clone()))
+    }
+
+    async fn get_sections(&self, project_id: &str) -> Result<Vec<Section>, ApiError> {
+        Ok(self.read(|data| {
+            data.sections
+                .iter()
+                .filter(|s| s.project_id == project_id)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn close_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        self.write(request_id, |data| {
+            let local = data.task_mut(task_id)?;
+            local.task.is_completed = true;
+            local.completed_at = Some(Local::now());
+            Ok(())
+        })
+    }
+
+    async fn reopen_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        self.write(request_id, |data| {
+            let local = data.task_mut(task_id)?;
+            local.task.is_completed = false;
+            local.completed_at = None;
+            Ok(())
+        })
+    }
+
+    async fn update_task(
+        &self,
+        task_id: &str,
+        update: &TaskUpdate,
+        request_id: &str,
+    ) -> Result<(), ApiError> {
+        self.write(request_id, |data| {
+            let task = &mut data.task_mut(task_id)?.task;
+            update_task_fields(task, update);
+            // Todoist works out the date of a due string; only the simplest
+            // ones are understood here.
+            if let Some(date) = update
+                .due_string
+                .as_deref()
+                .filter(|_| update.due_date.is_none())
+                .and_then(parse_due_string)
+            {
+                let date = date.format("%Y-%m-%d").to_string();
+                task.due = Some(Due {
+                    string: update.due_string.clone().unwrap_or_else(|| date.clone()),
+                    date,
+                    is_recurring: false,
+                    datetime: None,
+                    timezone: None,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    async fn reorder_tasks(&self, task_ids: &[String], request_id: &str) -> Result<(), ApiError> {
+        self.write(request_id, |data| {
+            // Tasks not in `task_ids` keep their place ahead of the others.
+            data.tasks
+                .sort_by_key(|t| task_ids.iter().position(|id| *id == t.task.id));
+            Ok(())
+        })
+    }
+
+    async fn quick_add_task(&self, text: &str, request_id: &str) -> Result<Task, ApiError> {
+        self.create(request_id, |data| {
+            let mut task = quick_add_preview("", text);
+            let project_name = text
+                .split_whitespace()
+                .find_map(|word| word.strip_prefix('#'));
+            if let Some(project) = project_name.and_then(|name| {
+                data.projects
+                    .iter()
+                    .find(|p| p.name.eq_ignore_ascii_case(name))
+            }) {
+                task.project_id = project.id.clone();
+            }
+            Ok(data.insert(task, request_id))
+        })
+    }
+
+    async fn create_task(&self, task: &Task, request_id: &str) -> Result<Task, ApiError> {
+        self.create(request_id, |data| Ok(data.insert(task.clone(), request_id)))
+    }
+
+    async fn delete_task(&self, task_id: &str, request_id: &str) -> Result<(), ApiError> {
+        self.write(request_id, |data| {
+            data.task_mut(task_id)?;
+            // Subtasks go with their parent, as on Todoist.
+            let mut deleted = vec![task_id.to_string()];
+            while let Some(position) = data.tasks.iter().position(|t| {
+                deleted.contains(&t.task.id)
+                    || t.task
+                        .parent_id
+                        .as_ref()
+                        .is_some_and(|p| deleted.contains(p))
+            }) {
+                deleted.push(data.tasks.remove(position).task.id);
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,9 +1,21 @@
 mod api;
+mod backend;
+mod conflict;
+mod local;
+#[cfg(test)]
+mod mock_server;
 mod state;
+mod store;
+mod sync;
+#[cfg(test)]
+mod tests;
 mod ui;
+mod views;
 
 use api::TodoistClient;
+use backend::Backend;
 use dotenv::dotenv;
+use local::LocalBackend;
 use state::AppState;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -14,42 +26,108 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok(); // Load environment variables from .env (ignore errors)
     env_logger::init();
 
+    // TODOIST_BACKEND=local keeps tasks in a file on this machine instead
+    if std::env::var("TODOIST_BACKEND").is_ok_and(|backend| backend == "local") {
+        let path = match std::env::var("TODOIST_LOCAL_FILE") {
+            Ok(path) => path.into(),
+            Err(_) => LocalBackend::default_path().ok_or("No data directory for local tasks")?,
+        };
+        return run(LocalBackend::open(path)?).await;
+    }
+
     // TODO: Get API token from environment or config
     let api_token =
         std::env::var("TODOIST_API_TOKEN").unwrap_or_else(|_| "placeholder_token".to_string());
 
-    let client = TodoistClient::new(api_token);
-    let client = Arc::new(client);
+    let mut client = TodoistClient::new(api_token);
+    // TODOIST_API_URL points the client elsewhere, e.g. at a local mock server
+    if let Ok(base_url) = std::env::var("TODOIST_API_URL") {
+        client = client.with_base_url(base_url);
+    }
+    if let Ok(limit) = std::env::var("TODOIST_PAGE_LIMIT") {
+        match limit.parse() {
+            Ok(limit) => client = client.with_page_limit(limit),
+            Err(e) => log::warn!("Ignoring invalid TODOIST_PAGE_LIMIT {:?}: {}", limit, e),
+        }
+    }
+    if let Ok(retries) = std::env::var("TODOIST_MAX_RETRIES") {
+        match retries.parse() {
+            Ok(max_retries) => {
+                client = client.with_retry_policy(api::RetryPolicy {
+                    max_retries,
+                    ..Default::default()
+                })
+            }
+            Err(e) => log::warn!("Ignoring invalid TODOIST_MAX_RETRIES {:?}: {}", retries, e),
+        }
+    }
+    // TODOIST_BACKEND=sync refreshes with incremental deltas from the Sync API
+    if std::env::var("TODOIST_BACKEND").is_ok_and(|backend| backend == "sync") {
+        client = client.with_incremental_sync(true);
+    }
+    run(client).await
+}
+
+/// Run the app on top of `backend` until the user quits.
+async fn run<B: Backend>(backend: B) -> Result<(), Box<dyn std::error::Error>> {
+    let backend = Arc::new(backend);
+
+    // Render from the local store right away; fresh data arrives in the
+    // background. Changes that never reached Todoist are replayed.
+    let mut app_state = match store::Store::open_default(backend.store_name()) {
+        Ok(store) => AppState::with_store(store),
+        Err(e) => {
+            log::warn!("Failed to open task store, keeping tasks in memory: {}", e);
+            AppState::new()
+        }
+    };
 
-    let app_state = AppState::new();
+    app_state.load_saved_views(views::load());
+    app_state.sync_status = state::SyncStatus::Syncing;
     let app_state = Arc::new(Mutex::new(app_state));
 
-    // Initial fetch of tasks
     {
-        let mut state = app_state.lock().await;
-        match client.get_todays_tasks().await {
-            Ok(tasks) => {
-                state.load_tasks(tasks);
-                state.sync_status = state::SyncStatus::Online;
-            }
-            Err(e) => {
-                eprintln!("Failed to fetch tasks: {}", e);
-                state.sync_status = state::SyncStatus::Error(e.to_string());
-            }
-        }
-        match client.get_todays_completed_tasks().await {
-            Ok(completed) => {
-                state.load_completed_tasks(completed);
-            }
-            Err(e) => {
-                eprintln!("Failed to fetch completed tasks: {}", e);
-            }
+        let app_state = app_state.clone();
+        let backend = backend.clone();
+        tokio::spawn(async move {
+            sync::refresh(&app_state, backend.as_ref()).await;
+        });
+    }
+
+    // Push queued changes in the background once their grace period ends
+    let flush_loop = sync::spawn_flush_loop(app_state.clone(), backend.clone());
+
+    // TODOIST_SYNC_INTERVAL sets how many seconds apart background refreshes
+    // are; 0 turns them off
+    let mut sync_interval = sync::DEFAULT_AUTO_SYNC_INTERVAL;
+    if let Ok(seconds) = std::env::var("TODOIST_SYNC_INTERVAL") {
+        match seconds.parse() {
+            Ok(seconds) => sync_interval = std::time::Duration::from_secs(seconds),
+            Err(e) => log::warn!(
+                "Ignoring invalid TODOIST_SYNC_INTERVAL {:?}: {}",
+                seconds,
+                e
+            ),
         }
     }
+    let auto_sync_loop = (!sync_interval.is_zero())
+        .then(|| sync::spawn_auto_sync_loop(app_state.clone(), backend.clone(), sync_interval));
 
     // Initialize and run UI
     let mut ui = UI::new()?;
-    ui.run(app_state.clone(), client.clone()).await?;
+    ui.run(app_state.clone(), backend.clone()).await?;
+    drop(ui); // Restore the terminal before printing anything
+
+    // Don't lose changes still inside their grace period on quit
+    flush_loop.abort();
+    if let Some(auto_sync_loop) = auto_sync_loop {
+        auto_sync_loop.abort();
+    }
+    let pending = app_state.lock().await.pending_changes.len();
+    if pending > 0 {
+        eprintln!("Syncing {} pending change(s)...", pending);
+        sync::flush_all(&app_state, backend.as_ref()).await;
+    }
 
     Ok(())
 }
@@ -0,0 +1,560 @@
+//! In-memory stand-in for the Todoist API, for tests
+//!
+//! Handles:
+//! - Serving active and completed tasks a page at a time, and no projects or
+//!   labels
+//! - Creating (including quick-add), closing, reopening, updating and
+//!   deleting tasks
+//! - The Sync API: full syncs and deltas of changed tasks, and the item
+//!   commands, each with its own status, and their `temp_id` mapping
+//! - Rejecting requests without the expected API token
+//! - Failing requests on demand, to exercise retries and error handling
+
+use crate::api::{RetryPolicy, Task, TaskUpdate, TodoistClient};
+use crate::state::{quick_add_preview, update_task_fields, NO_DUE_DATE};
+use axum::extract::{Path, Query, State};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// The token `MockTodoist::client` authenticates with.
+pub const MOCK_API_TOKEN: &str = "mock-token";
+
+/// A Todoist API server on a local port, holding its data in memory.
+pub struct MockTodoist {
+    base_url: String,
+    data: Arc<Mutex<MockData>>,
+    server: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct MockData {
+    tasks: Vec<Task>,
+    /// Responses to give instead of handling the next requests; `None`
+    /// handles that request as usual.
+    failures: VecDeque<Option<(StatusCode, Option<u64>)>>,
+    /// "METHOD /path" of every request received, in order.
+    requests: Vec<String>,
+    /// How many tasks have been created, for numbering new ones.
+    created: usize,
+    /// Bumped on every change to a task; handed out as the sync token.
+    revision: usize,
+    /// The revision each task last changed at, deleted ones included.
+    changed_at: HashMap<String, usize>,
+    /// Deleted tasks, reported as such in later deltas.
+    deleted: Vec<Task>,
+}
+
+impl MockData {
+    /// Note that a task changed, for the next delta.
+    fn touch(&mut self, task_id: &str) {
+        self.revision += 1;
+        self.changed_at.insert(task_id.to_string(), self.revision);
+    }
+
+    /// Store a new task under the next free ID and return it.
+    fn add(&mut self, task: Task) -> Task {
+        self.created += 1;
+        let task = Task {
+            id: format!("created-{}", self.created),
+            ..task
+        };
+        self.touch(&task.id);
+        self.tasks.push(task.clone());
+        task
+    }
+
+    fn update(&mut self, task_id: &str, update: &TaskUpdate) -> Option<Task> {
+        let task = self.tasks.iter_mut().find(|t| t.id == task_id)?;
+        update_task_fields(task, update);
+        let task = task.clone();
+        self.touch(task_id);
+        Some(task)
+    }
+
+    fn set_completed(&mut self, task_id: &str, completed: bool) -> bool {
+        let Some(task) = self.tasks.iter_mut().find(|t| t.id == task_id) else {
+            return false;
+        };
+        task.is_completed = completed;
+        self.touch(task_id);
+        true
+    }
+
+    /// Delete a task along with its subtasks, as Todoist does.
+    fn remove(&mut self, task_id: &str) -> bool {
+        let Some(position) = self.tasks.iter().position(|t| t.id == task_id) else {
+            return false;
+        };
+        let task = self.tasks.remove(position);
+        self.deleted.push(task);
+        self.touch(task_id);
+        let subtasks: Vec<String> = self
+            .tasks
+            .iter()
+            .filter(|t| t.parent_id.as_deref() == Some(task_id))
+            .map(|t| t.id.clone())
+            .collect();
+        for subtask in subtasks {
+            self.remove(&subtask);
+        }
+        true
+    }
+
+    /// Run one Sync API command, returning the ID of the task it created,
+    /// if it created one.
+    fn run_command(&mut self, kind: &str, args: &Value) -> Result<Option<String>, String> {
+        let task_id = args["id"].as_str().unwrap_or_default();
+        let found = match kind {
+            "item_add" => {
+                let new: NewTask =
+                    serde_json::from_value(args.clone()).map_err(|e| e.to_string())?;
+                let due = args.get("due").map(due_update).unwrap_or_default();
+                let task = new.into_task(due);
+                return Ok(Some(self.add(task).id));
+            }
+            "item_update" => {
+                let mut update: TaskUpdate =
+                    serde_json::from_value(args.clone()).map_err(|e| e.to_string())?;
+                if let Some(due) = args.get("due") {
+                    let due = due_update(due);
+                    update.due_string = due.due_string;
+                    update.due_date = due.due_date;
+                }
+                self.update(task_id, &update).is_some()
+            }
+            "item_close" => self.set_completed(task_id, true),
+            "item_uncomplete" => self.set_completed(task_id, false),
+            "item_delete" => self.remove(task_id),
+            "item_update_day_orders" => true,
+            _ => return Err(format!("unknown command {}", kind)),
+        };
+        if found {
+            Ok(None)
+        } else {
+            Err("Item not found".to_string())
+        }
+    }
+
+    /// The tasks changed since revision `since` as Sync API items, or every
+    /// active one for a full sync.
+    fn sync_items(&self, since: Option<usize>) -> Vec<Value> {
+        let item = |task: &Task, is_deleted: bool| {
+            let mut item = json!(task);
+            item["is_deleted"] = json!(is_deleted);
+            if task.is_completed {
+                item["completed_at"] = json!(chrono::Utc::now().to_rfc3339());
+            }
+            item
+        };
+        let Some(since) = since else {
+            return self
+                .tasks
+                .iter()
+                .filter(|t| !t.is_completed)
+                .map(|t| item(t, false))
+                .collect();
+        };
+        let changed = |task: &&Task| self.changed_at.get(&task.id).is_some_and(|&r| r > since);
+        let live = self.tasks.iter().filter(changed).map(|t| item(t, false));
+        let deleted = self.deleted.iter().filter(changed).map(|t| item(t, true));
+        live.chain(deleted).collect()
+    }
+}
+
+type SharedData = Arc<Mutex<MockData>>;
+
+#[derive(Debug, Default, Deserialize)]
+struct ListQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    project_id: Option<String>,
+    label: Option<String>,
+}
+
+impl MockTodoist {
+    /// Start serving `tasks` on a free local port.
+    pub async fn start(tasks: Vec<Task>) -> Self {
+        let data = Arc::new(Mutex::new(MockData {
+            tasks,
+            ..Default::default()
+        }));
+        let app = Router::new()
+            .route("/tasks", get(list_tasks).post(create_task))
+            .route("/tasks/quick", post(quick_add_task))
+            .route("/tasks/filter", get(list_active_tasks))
+            .route("/tasks/completed/by_completion_date", get(list_completed))
+            .route("/tasks/:id", post(update_task).delete(delete_task))
+            .route("/tasks/:id/close", post(close_task))
+            .route("/tasks/:id/reopen", post(reopen_task))
+            .route("/projects", get(list_nothing))
+            .route("/labels", get(list_nothing))
+            .route("/sync", post(sync))
+            .layer(middleware::from_fn_with_state(data.clone(), guard))
+            .with_state(data.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .expect("listen on mock server port")
+            .serve(app.into_make_service());
+        let server = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Mock Todoist server stopped: {}", e);
+            }
+        });
+        Self {
+            base_url,
+            data,
+            server,
+        }
+    }
+
+    /// A client pointed at this server, with retries that don't slow tests
+    /// down.
+    pub fn client(&self) -> TodoistClient {
+        TodoistClient::new(MOCK_API_TOKEN.to_string())
+            .with_base_url(self.base_url.clone())
+            .with_retry_policy(RetryPolicy {
+                base_delay: Duration::from_millis(10),
+                request_timeout: Duration::from_secs(2),
+                ..Default::default()
+            })
+    }
+
+    /// Answer the next request with `status` (and a `Retry-After` of that
+    /// many seconds) instead of handling it.
+    pub fn fail_next(&self, status: StatusCode, retry_after: Option<u64>) {
+        self.data
+            .lock()
+            .unwrap()
+            .failures
+            .push_back(Some((status, retry_after)));
+    }
+
+    /// Handle the next request as usual, even with failures queued after it.
+    pub fn pass_next(&self) {
+        self.data.lock().unwrap().failures.push_back(None);
+    }
+
+    /// The server's current copy of a task.
+    pub fn task(&self, task_id: &str) -> Option<Task> {
+        let data = self.data.lock().unwrap();
+        data.tasks.iter().find(|t| t.id == task_id).cloned()
+    }
+
+    /// Change a task on the server, as if from another device.
+    pub fn edit_task(&self, task_id: &str, update: &TaskUpdate) {
+        self.data.lock().unwrap().update(task_id, update);
+    }
+
+    /// Delete a task on the server, as if from another device.
+    pub fn delete_task(&self, task_id: &str) {
+        self.data.lock().unwrap().remove(task_id);
+    }
+
+    /// "METHOD /path" of every request received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.data.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockTodoist {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Log the request, then fail it if asked to or if it lacks the API token.
+async fn guard<B>(State(data): State<SharedData>, request: Request<B>, next: Next<B>) -> Response {
+    let failure = {
+        let mut data = data.lock().unwrap();
+        data.requests
+            .push(format!("{} {}", request.method(), request.uri().path()));
+        data.failures.pop_front().flatten()
+    };
+    if let Some((status, retry_after)) = failure {
+        let mut response = status.into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, seconds.to_string().parse().unwrap());
+        }
+        return response;
+    }
+
+    let expected = format!("Bearer {}", MOCK_API_TOKEN);
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .is_some_and(|value| value.as_bytes() == expected.as_bytes());
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+/// One page of `items`, starting at the offset in the cursor.
+fn page<T: serde::Serialize>(
+    items: Vec<T>,
+    query: &ListQuery,
+    key: &str,
+) -> Json<serde_json::Value> {
+    let start: usize = query
+        .cursor
+        .as_deref()
+        .and_then(|cursor| cursor.parse().ok())
+        .unwrap_or(0);
+    let end = (start + query.limit.unwrap_or(50)).min(items.len());
+    let next_cursor = (end < items.len()).then(|| end.to_string());
+    let results: Vec<T> = items
+        .into_iter()
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect();
+    Json(json!({ key: results, "next_cursor": next_cursor }))
+}
+
+/// Active tasks, optionally in one project or with one label.
+async fn list_tasks(
+    State(data): State<SharedData>,
+    Query(query): Query<ListQuery>,
+) -> Json<serde_json::Value> {
+    let tasks: Vec<Task> = data
+        .lock()
+        .unwrap()
+        .tasks
+        .iter()
+        .filter(|t| !t.is_completed)
+        .filter(|t| {
+            query
+                .project_id
+                .as_ref()
+                .is_none_or(|id| t.project_id == *id)
+        })
+        .filter(|t| query.label.as_ref().is_none_or(|l| t.labels.contains(l)))
+        .cloned()
+        .collect();
+    page(tasks, &query, "results")
+}
+
+/// Every active task: the mock doesn't evaluate filter queries.
+async fn list_active_tasks(
+    State(data): State<SharedData>,
+    Query(query): Query<ListQuery>,
+) -> Json<serde_json::Value> {
+    list_tasks(
+        State(data),
+        Query(ListQuery {
+            project_id: None,
+            label: None,
+            ..query
+        }),
+    )
+    .await
+}
+
+/// Every completed task, whenever it was completed.
+async fn list_completed(
+    State(data): State<SharedData>,
+    Query(query): Query<ListQuery>,
+) -> Json<serde_json::Value> {
+    let tasks: Vec<Task> = data
+        .lock()
+        .unwrap()
+        .tasks
+        .iter()
+        .filter(|t| t.is_completed)
+        .cloned()
+        .collect();
+    page(tasks, &query, "items")
+}
+
+async fn close_task(State(data): State<SharedData>, Path(task_id): Path<String>) -> StatusCode {
+    set_completed(&data, &task_id, true)
+}
+
+async fn reopen_task(State(data): State<SharedData>, Path(task_id): Path<String>) -> StatusCode {
+    set_completed(&data, &task_id, false)
+}
+
+async fn update_task(
+    State(data): State<SharedData>,
+    Path(task_id): Path<String>,
+    Json(update): Json<TaskUpdate>,
+) -> Response {
+    match data.lock().unwrap().update(&task_id, &update) {
+        Some(task) => Json(task).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// The fields of a new task Todoist takes on `POST /tasks`, or in an
+/// `item_add` command.
+#[derive(Debug, Deserialize)]
+struct NewTask {
+    content: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    project_id: String,
+    section_id: Option<String>,
+    parent_id: Option<String>,
+    priority: Option<u8>,
+    due_string: Option<String>,
+    #[serde(alias = "due_datetime")]
+    due_date: Option<String>,
+}
+
+impl NewTask {
+    /// The task to store, due as `due` says if it sets a due date, or as
+    /// the REST due fields do otherwise.
+    fn into_task(self, due: TaskUpdate) -> Task {
+        let mut task = Task {
+            project_id: self.project_id,
+            section_id: self.section_id,
+            parent_id: self.parent_id,
+            content: self.content,
+            description: self.description,
+            labels: self.labels,
+            priority: self.priority.unwrap_or(1),
+            ..Default::default()
+        };
+        let due = match due {
+            TaskUpdate {
+                due_string: None,
+                due_date: None,
+                ..
+            } => TaskUpdate {
+                due_string: self.due_string,
+                due_date: self.due_date,
+                ..Default::default()
+            },
+            due => due,
+        };
+        update_task_fields(&mut task, &due);
+        task
+    }
+}
+
+async fn create_task(State(data): State<SharedData>, Json(new): Json<NewTask>) -> Json<Task> {
+    let task = new.into_task(TaskUpdate::default());
+    Json(data.lock().unwrap().add(task))
+}
+
+#[derive(Debug, Deserialize)]
+struct QuickAdd {
+    text: String,
+}
+
+/// Todoist's quick-add, understood as far as `quick_add_preview` goes.
+async fn quick_add_task(State(data): State<SharedData>, Json(body): Json<QuickAdd>) -> Json<Task> {
+    let task = quick_add_preview("", &body.text);
+    Json(data.lock().unwrap().add(task))
+}
+
+async fn delete_task(State(data): State<SharedData>, Path(task_id): Path<String>) -> StatusCode {
+    match data.lock().unwrap().remove(&task_id) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+fn set_completed(data: &SharedData, task_id: &str, completed: bool) -> StatusCode {
+    match data.lock().unwrap().set_completed(task_id, completed) {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+/// A Sync API command as the client sends it.
+#[derive(Debug, Deserialize)]
+struct Command {
+    #[serde(rename = "type")]
+    kind: String,
+    uuid: String,
+    temp_id: Option<String>,
+    args: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncRequest {
+    sync_token: Option<String>,
+    #[serde(default)]
+    commands: Vec<Command>,
+}
+
+/// Run the commands in order, then answer with the tasks changed since the
+/// sync token, if one was sent. An unknown token (such as `*`) gets a full
+/// sync.
+async fn sync(State(data): State<SharedData>, Json(request): Json<SyncRequest>) -> Json<Value> {
+    let mut data = data.lock().unwrap();
+    let mut sync_status = serde_json::Map::new();
+    let mut temp_id_mapping = serde_json::Map::new();
+    for command in request.commands {
+        let mut args = command.args;
+        // A later command may name a task by an earlier one's temp_id.
+        for field in ["id", "parent_id"] {
+            if let Some(real_id) = args[field].as_str().and_then(|id| temp_id_mapping.get(id)) {
+                args[field] = real_id.clone();
+            }
+        }
+        let status = match data.run_command(&command.kind, &args) {
+            Ok(created) => {
+                if let (Some(temp_id), Some(real_id)) = (command.temp_id, created) {
+                    temp_id_mapping.insert(temp_id, json!(real_id));
+                }
+                json!("ok")
+            }
+            Err(error) => json!({ "error": error }),
+        };
+        sync_status.insert(command.uuid, status);
+    }
+
+    let mut response = json!({
+        "sync_token": data.revision.to_string(),
+        "sync_status": sync_status,
+        "temp_id_mapping": temp_id_mapping,
+    });
+    if let Some(token) = request.sync_token {
+        let since = token.parse().ok();
+        response["full_sync"] = json!(since.is_none());
+        response["items"] = json!(data.sync_items(since));
+        response["projects"] = json!([]);
+        response["labels"] = json!([]);
+    }
+    Json(response)
+}
+
+/// The due fields of a Sync API `due` argument as an update; `null` clears
+/// the due date.
+fn due_update(due: &Value) -> TaskUpdate {
+    match due {
+        Value::Null => TaskUpdate {
+            due_string: Some(NO_DUE_DATE.to_string()),
+            ..Default::default()
+        },
+        Value::Object(_) => TaskUpdate {
+            due_string: due["string"].as_str().map(String::from),
+            due_date: due["date"].as_str().map(String::from),
+            ..Default::default()
+        },
+        _ => TaskUpdate::default(),
+    }
+}
+
+async fn list_nothing(Query(query): Query<ListQuery>) -> Json<serde_json::Value> {
+    page(Vec::<serde_json::Value>::new(), &query, "results")
+}
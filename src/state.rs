@@ -6,17 +6,345 @@
 //! - Offline mode and sync status
 //! - Undo functionality
 
-use crate::api::Task;
-use chrono::{DateTime, Local, NaiveDate};
+use crate::api::{ApiError, Due, Label, Project, Section, SyncResponse, Task, TaskUpdate};
+use crate::conflict::{combine_updates, Conflict};
+use crate::store::{Store, TaskList};
+use crate::views::{self, SavedView};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a local change is held before it is pushed to Todoist.
+pub const SYNC_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Maximum number of changes kept in the undo history.
+const MAX_UNDO_HISTORY: usize = 100;
 
 #[derive(Clone)]
 pub struct AppState {
+    /// Today's active tasks: the stored ones with pending changes applied.
     pub tasks: Vec<Task>,
+    /// Tasks completed today: the stored ones with pending changes applied.
     pub completed_tasks: Vec<Task>,
+    pub projects: Vec<Project>,
+    pub labels: Vec<Label>,
+    /// Named filter queries, listed in the sidebar under Today.
+    pub saved_views: Vec<SavedView>,
+    /// Active tasks of the project, label or filter shown instead of Today.
+    pub view_tasks: Vec<Task>,
+    /// Sections of the project shown in `View::Project`.
+    pub sections: Vec<Section>,
+    /// IDs of sections collapsed with `za`.
+    pub folded_sections: HashSet<String>,
+    /// IDs of tasks whose subtasks are collapsed with `za`.
+    pub folded_tasks: HashSet<String>,
+    pub view: View,
+    pub sidebar: Sidebar,
     pub selected_index: usize,
     pub search_query: String,
     pub is_searching: bool,
+    pub input_mode: InputMode,
+    /// Text typed into the prompt while not in `InputMode::Normal`.
+    pub input_buffer: String,
+    /// The task being edited while in `InputMode::EditTask`.
+    pub edit_form: Option<EditForm>,
     pub sync_status: SyncStatus,
+    /// When a failed API request will be retried, while one is backing off.
+    pub retrying_at: Option<Instant>,
+    /// When the stored task lists were last refreshed from the server.
+    pub refreshed_at: Option<DateTime<Utc>>,
+    /// Where the last incremental sync left off; `None` means start over.
+    pub sync_token: Option<String>,
+    pub pending_changes: Vec<PendingChange>,
+    /// Changes taken from the queue and being pushed right now. A refresh
+    /// that lands mid-push applies them along with the pending ones.
+    pub in_flight: Vec<PendingChange>,
+    /// Undo history; each step holds the changes one user action made.
+    pub undo_stack: Vec<Vec<Change>>,
+    pub redo_stack: Vec<Vec<Change>>,
+    /// Tasks whose queued edits and server copy changed the same fields.
+    /// Their edits are held back until the user picks what to keep.
+    pub conflicts: Vec<Conflict>,
+    /// The copy of each task with queued edits that the first of them was
+    /// made against, by task ID.
+    pub task_bases: HashMap<String, Task>,
+    /// What the server is known to have, plus the queued changes; the
+    /// source the task lists above are read from.
+    pub store: Store,
+}
+
+/// A local mutation that has not been pushed to Todoist yet.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    Complete {
+        task_id: String,
+    },
+    Reopen {
+        task_id: String,
+    },
+    Edit {
+        task_id: String,
+        update: TaskUpdate,
+        previous: TaskUpdate,
+    },
+    Reorder {
+        task_ids: Vec<String>,
+        previous: Vec<String>,
+    },
+    /// Create a task from quick-add text. Until the server assigns a real ID
+    /// the task lives under `temp_id`.
+    Create {
+        temp_id: String,
+        text: String,
+    },
+    /// Delete a task. The removed task is kept as a tombstone so the deletion
+    /// can be undone.
+    Delete {
+        task: Box<Task>,
+    },
+    /// Bring a deleted task back. Once the deletion has synced this re-creates
+    /// the task on the server, where it gets a new ID.
+    Restore {
+        task: Box<Task>,
+    },
+}
+
+impl Change {
+    /// The change that reverses this one, if there is one.
+    pub fn inverse(&self) -> Option<Change> {
+        match self {
+            Change::Complete { task_id } => Some(Change::Reopen {
+                task_id: task_id.clone(),
+            }),
+            Change::Reopen { task_id } => Some(Change::Complete {
+                task_id: task_id.clone(),
+            }),
+            Change::Edit {
+                task_id,
+                update,
+                previous,
+            } => Some(Change::Edit {
+                task_id: task_id.clone(),
+                update: previous.clone(),
+                previous: update.clone(),
+            }),
+            Change::Reorder { task_ids, previous } => Some(Change::Reorder {
+                task_ids: previous.clone(),
+                previous: task_ids.clone(),
+            }),
+            Change::Create { .. } => None,
+            Change::Delete { task } => Some(Change::Restore { task: task.clone() }),
+            Change::Restore { task } => Some(Change::Delete { task: task.clone() }),
+        }
+    }
+
+    /// The ID a task goes by locally until the server creates it and assigns
+    /// its real one, for changes that create a task.
+    pub fn created_task_id(&self) -> Option<&str> {
+        match self {
+            Change::Create { temp_id, .. } => Some(temp_id),
+            Change::Restore { task } => Some(&task.id),
+            _ => None,
+        }
+    }
+
+    /// Point every reference to task `from` at task `to` instead, e.g. once a
+    /// created task's temporary ID has been replaced by the real one.
+    pub fn replace_task_id(&mut self, from: &str, to: &str) {
+        let replace = |id: &mut String| {
+            if id == from {
+                *id = to.to_string();
+            }
+        };
+        match self {
+            Change::Complete { task_id }
+            | Change::Reopen { task_id }
+            | Change::Edit { task_id, .. } => replace(task_id),
+            Change::Reorder { task_ids, previous } => task_ids
+                .iter_mut()
+                .chain(previous.iter_mut())
+                .for_each(replace),
+            Change::Create { temp_id, .. } => replace(temp_id),
+            Change::Delete { task } | Change::Restore { task } => {
+                replace(&mut task.id);
+                if let Some(parent_id) = &mut task.parent_id {
+                    replace(parent_id);
+                }
+            }
+        }
+    }
+}
+
+/// A queued change together with the time it was made.
+#[derive(Debug, Clone)]
+pub struct PendingChange {
+    /// Idempotency key, sent along with the change so retries are safe.
+    pub key: Uuid,
+    pub change: Change,
+    pub queued_at: Instant,
+}
+
+/// Which tasks the main pane shows.
+#[derive(Debug, PartialEq, Clone)]
+pub enum View {
+    Today,
+    /// All active tasks of the project with this ID.
+    Project(String),
+    /// All active tasks carrying the label with this name.
+    Label(String),
+    /// All active tasks matching this Todoist filter query.
+    Filter(String),
+}
+
+/// A line of the project view: a section header or a task.
+#[derive(Debug, Clone, Copy)]
+pub enum ProjectRow<'a> {
+    Section {
+        section: &'a Section,
+        task_count: usize,
+        folded: bool,
+    },
+    Task(&'a Task),
+}
+
+/// The collapsible project list on the left. Entry 0 is "Today", followed by
+/// one entry per saved view, per project and then per label.
+#[derive(Debug, Clone, Default)]
+pub struct Sidebar {
+    pub open: bool,
+    /// Whether j/k/Enter drive the sidebar rather than the task list.
+    pub focused: bool,
+    pub selected: usize,
+}
+
+/// What keystrokes currently drive: list navigation or a text prompt.
+#[derive(Debug, PartialEq, Clone)]
+pub enum InputMode {
+    Normal,
+    AddTask,
+    EditTask,
+    /// Typing a `:` command such as `filter p1 & #Work`.
+    Command,
+    /// Typing a replacement for an API token Todoist rejected.
+    EnterToken,
+    /// Waiting for y/n before deleting the task with this ID.
+    ConfirmDelete(String),
+    /// Waiting for y/n before completing the task with this ID, which still
+    /// has open subtasks.
+    ConfirmComplete(String),
+    /// Choosing between queued edits and the server's changes to the task
+    /// of the first entry in `conflicts`.
+    ResolveConflict,
+    /// Editing a task's content and description in `$EDITOR`. Holds the task
+    /// as it was when the editor opened.
+    ExternalEditor(Box<Task>),
+    /// Waiting for y/n before moving every overdue task this many days from
+    /// today.
+    ConfirmReschedule(i64),
+}
+
+/// Labels of the fields in the edit form, in display order.
+pub const EDIT_FORM_FIELDS: [&str; 4] = ["Content", "Description", "Due", "Priority"];
+
+/// In-progress edits to a single task.
+#[derive(Debug, Clone)]
+pub struct EditForm {
+    pub task_id: String,
+    /// Current text of each field, indexed like `EDIT_FORM_FIELDS`.
+    pub fields: [String; 4],
+    pub focused: usize,
+    original: [String; 4],
+}
+
+impl EditForm {
+    fn new(task: &Task) -> Self {
+        let fields = [
+            task.content.clone(),
+            task.description.clone(),
+            task.due
+                .as_ref()
+                .map(|d| d.string.clone())
+                .unwrap_or_default(),
+            // Todoist shows priority 4 (most urgent) as "p1".
+            format!("p{}", 5 - task.priority.clamp(1, 4)),
+        ];
+        Self {
+            task_id: task.id.clone(),
+            original: fields.clone(),
+            fields,
+            focused: 0,
+        }
+    }
+
+    pub fn focused_field_mut(&mut self) -> &mut String {
+        &mut self.fields[self.focused]
+    }
+
+    pub fn focus_next(&mut self) {
+        self.focused = (self.focused + 1) % self.fields.len();
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.focused = (self.focused + self.fields.len() - 1) % self.fields.len();
+    }
+
+    /// Builds the update for the fields that changed, plus the update that
+    /// would restore them. Returns `None` if nothing changed.
+    fn to_change(&self) -> Option<Change> {
+        let mut update = TaskUpdate::default();
+        let mut previous = TaskUpdate::default();
+        let changed = |i: usize| self.fields[i].trim() != self.original[i].trim();
+
+        if changed(0) && !self.fields[0].trim().is_empty() {
+            update.content = Some(self.fields[0].trim().to_string());
+            previous.content = Some(self.original[0].clone());
+        }
+        if changed(1) {
+            update.description = Some(self.fields[1].trim().to_string());
+            previous.description = Some(self.original[1].clone());
+        }
+        if changed(2) {
+            update.due_string = Some(due_string_or_none(&self.fields[2]));
+            previous.due_string = Some(due_string_or_none(&self.original[2]));
+        }
+        if changed(3) {
+            if let Some(priority) = parse_priority(&self.fields[3]) {
+                update.priority = Some(priority);
+                previous.priority = parse_priority(&self.original[3]);
+            }
+        }
+
+        if update == TaskUpdate::default() {
+            return None;
+        }
+        Some(Change::Edit {
+            task_id: self.task_id.clone(),
+            update,
+            previous,
+        })
+    }
+}
+
+/// Todoist clears a due date when given the due string "no date".
+pub const NO_DUE_DATE: &str = "no date";
+
+fn due_string_or_none(field: &str) -> String {
+    let field = field.trim();
+    if field.is_empty() {
+        NO_DUE_DATE.to_string()
+    } else {
+        field.to_string()
+    }
+}
+
+/// Parses "p1"–"p4" (or just "1"–"4") into the API's priority scale.
+fn parse_priority(field: &str) -> Option<u8> {
+    let field = field.trim().to_lowercase();
+    let n: u8 = field.strip_prefix('p').unwrap_or(&field).parse().ok()?;
+    (1..=4).contains(&n).then(|| 5 - n)
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -24,68 +352,1436 @@ pub enum SyncStatus {
     Online,
     Offline,
     Syncing,
+    /// Todoist rejected the API token; a new one is needed.
+    Unauthorized,
+    /// Todoist asked us to slow down, for this long if it said.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
     Error(String),
 }
 
+impl SyncStatus {
+    /// The status to show after an API call failed with `error`.
+    pub fn from_error(error: &ApiError) -> Self {
+        match error {
+            ApiError::Network(_) => SyncStatus::Offline,
+            ApiError::Unauthorized => SyncStatus::Unauthorized,
+            ApiError::RateLimited { retry_after } => SyncStatus::RateLimited {
+                retry_after: *retry_after,
+            },
+            e => SyncStatus::Error(e.to_string()),
+        }
+    }
+
+    /// Whether pushes are on hold until the next retry rather than going out
+    /// as soon as they are due.
+    pub fn is_waiting_to_retry(&self) -> bool {
+        matches!(
+            self,
+            SyncStatus::Offline | SyncStatus::Unauthorized | SyncStatus::RateLimited { .. }
+        )
+    }
+}
+
 impl AppState {
     pub fn new() -> Self {
         Self {
             tasks: Vec::new(),
             completed_tasks: Vec::new(),
+            projects: Vec::new(),
+            labels: Vec::new(),
+            saved_views: Vec::new(),
+            view_tasks: Vec::new(),
+            sections: Vec::new(),
+            folded_sections: HashSet::new(),
+            folded_tasks: HashSet::new(),
+            view: View::Today,
+            sidebar: Sidebar::default(),
             selected_index: 0,
             search_query: String::new(),
             is_searching: false,
+            input_mode: InputMode::Normal,
+            input_buffer: String::new(),
+            edit_form: None,
             sync_status: SyncStatus::Offline,
+            retrying_at: None,
+            refreshed_at: None,
+            pending_changes: Vec::new(),
+            in_flight: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            conflicts: Vec::new(),
+            task_bases: HashMap::new(),
+            sync_token: None,
+            store: Store::open_in_memory().expect("Failed to open in-memory store"),
         }
     }
 
-    /// Load tasks into the application state
-    pub fn load_tasks(&mut self, tasks: Vec<Task>) {
-        self.tasks = tasks;
-        self.selected_index = 0;
+    /// State read from `store`: the last known tasks, projects and labels,
+    /// with the changes that never reached the server (crash, or quit while
+    /// offline) queued again, ready to be flushed at once.
+    pub fn with_store(store: Store) -> Self {
+        let mut state = Self {
+            store,
+            ..Self::new()
+        };
+        state.projects = stored(state.store.projects(), "projects");
+        state.labels = stored(state.store.labels(), "labels");
+        state.sync_token = stored(state.store.sync_token(), "sync token");
+        state.refreshed_at = stored(state.store.refreshed_at(), "refresh time");
+        state.task_bases = stored(state.store.task_bases(), "task bases")
+            .into_iter()
+            .map(|task| (task.id.clone(), task))
+            .collect();
+        let outstanding = stored(state.store.pending_changes(), "pending changes");
+        if !outstanding.is_empty() {
+            log::info!("Recovered {} unsynced change(s)", outstanding.len());
+        }
+        state.restore_pending_changes(outstanding);
+        state.reload_tasks();
+        state
+    }
+
+    /// Merge a page of fetched tasks into the stored `list`. The first page
+    /// starts a refresh of the list; `finish_task_refresh` then drops the
+    /// tasks it didn't return.
+    pub fn load_task_page(&mut self, list: TaskList, tasks: Vec<Task>, first: bool) {
+        self.keeping_selection(|state| state.merge_task_page(list, tasks, first));
     }
 
-    /// Load completed tasks into the application state
-    pub fn load_completed_tasks(&mut self, tasks: Vec<Task>) {
-        // Force each completed task's is_completed flag to true.
-        self.completed_tasks = tasks
+    fn merge_task_page(&mut self, list: TaskList, tasks: Vec<Task>, first: bool) {
+        if first {
+            log_store_error(self.store.begin_task_refresh(list));
+        }
+        let tasks: Vec<Task> = tasks
             .into_iter()
             .map(|mut t| {
-                t.is_completed = true;
+                // Force each completed task's is_completed flag to true.
+                t.is_completed |= list == TaskList::Completed;
                 t
             })
             .collect();
+        self.detect_conflicts(&tasks);
+        log_store_error(self.store.merge_tasks(list, &tasks));
+        self.reload_tasks();
+    }
+
+    /// Drop the tasks a refresh of `list` didn't return.
+    pub fn finish_task_refresh(&mut self, list: TaskList) {
+        self.keeping_selection(|state| {
+            log_store_error(state.store.finish_task_refresh(list));
+            state.reload_tasks();
+        });
+    }
+
+    /// Record that the task lists were just refreshed from the server.
+    pub fn mark_refreshed(&mut self) {
+        match self.store.mark_refreshed() {
+            Ok(refreshed_at) => self.refreshed_at = Some(refreshed_at),
+            Err(e) => log::error!("Failed to update store: {}", e),
+        }
+    }
+
+    /// Read the task lists back from the store and apply pending changes.
+    fn reload_tasks(&mut self) {
+        self.tasks = stored(self.store.tasks(TaskList::Active), "tasks");
+        self.completed_tasks = stored(self.store.tasks(TaskList::Completed), "tasks");
+        self.reapply_pending_changes();
+        self.clamp_selection();
+    }
+
+    /// Load the user's projects into the application state
+    pub fn load_projects(&mut self, projects: Vec<Project>) {
+        log_store_error(self.store.replace_projects(&projects));
+        self.projects = projects;
+        self.clamp_sidebar_selection();
     }
 
-    /// Returns the number of tasks in the unified today view.
+    /// Load the user's labels into the application state
+    pub fn load_labels(&mut self, mut labels: Vec<Label>) {
+        labels.sort_by_key(|l| l.order);
+        log_store_error(self.store.replace_labels(&labels));
+        self.labels = labels;
+        self.clamp_sidebar_selection();
+    }
+
+    /// Apply what the Sync API reported: on a full sync it replaces the task
+    /// lists, projects and labels, otherwise each changed item, project and
+    /// label is updated in place, added or dropped.
+    pub fn apply_sync_response(&mut self, response: SyncResponse) {
+        self.keeping_selection(|state| state.merge_sync_response(response));
+    }
+
+    fn merge_sync_response(&mut self, response: SyncResponse) {
+        // Work on what the server had, without pending changes applied.
+        self.tasks = stored(self.store.tasks(TaskList::Active), "tasks");
+        self.completed_tasks = stored(self.store.tasks(TaskList::Completed), "tasks");
+        if response.full_sync {
+            self.tasks.clear();
+            self.projects.clear();
+            self.labels.clear();
+        }
+
+        let updated: Vec<Task> = response
+            .items
+            .iter()
+            .filter(|item| !item.is_deleted)
+            .map(|item| item.resource.clone())
+            .collect();
+        self.detect_conflicts(&updated);
+
+        let today = Local::now().date_naive();
+        for item in response.items {
+            let task = item.resource;
+            let id = task.id.clone();
+            if item.is_deleted {
+                self.tasks.retain(|t| t.id != id);
+                self.completed_tasks.retain(|t| t.id != id);
+                self.view_tasks.retain(|t| t.id != id);
+                continue;
+            }
+
+            if task.is_completed {
+                self.tasks.retain(|t| t.id != id);
+                self.view_tasks.retain(|t| t.id != id);
+                let completed_today = item
+                    .completed_at
+                    .as_deref()
+                    .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+                    .is_some_and(|at| at.with_timezone(&Local).date_naive() == today);
+                if completed_today {
+                    upsert_task(&mut self.completed_tasks, task);
+                } else {
+                    self.completed_tasks.retain(|t| t.id != id);
+                }
+                continue;
+            }
+
+            self.completed_tasks.retain(|t| t.id != id);
+            // Filter queries can't be evaluated locally, so only tasks already
+            // in a filter view are kept up to date there.
+            let in_view = match self.view {
+                View::Filter(_) => self.view_tasks.iter().any(|t| t.id == id),
+                _ => self.shows_in_view(&task),
+            };
+            if in_view {
+                upsert_task(&mut self.view_tasks, task.clone());
+            } else {
+                self.view_tasks.retain(|t| t.id != id);
+            }
+            // Mirror the REST "today" filter, which includes overdue tasks.
+            if due_date(&task).is_some_and(|d| d <= today) {
+                upsert_task(&mut self.tasks, task);
+            } else {
+                self.tasks.retain(|t| t.id != id);
+            }
+        }
+
+        for project in response.projects {
+            let id = project.resource.id.clone();
+            match self.projects.iter_mut().find(|p| p.id == id) {
+                _ if project.is_deleted || project.is_archived => {
+                    self.projects.retain(|p| p.id != id)
+                }
+                Some(slot) => *slot = project.resource,
+                None => self.projects.push(project.resource),
+            }
+        }
+
+        for label in response.labels {
+            let id = label.resource.id.clone();
+            match self.labels.iter_mut().find(|l| l.id == id) {
+                _ if label.is_deleted => self.labels.retain(|l| l.id != id),
+                Some(slot) => *slot = label.resource,
+                None => self.labels.push(label.resource),
+            }
+        }
+        self.labels.sort_by_key(|l| l.order);
+
+        log_store_error(self.store.replace_tasks(TaskList::Active, &self.tasks));
+        log_store_error(
+            self.store
+                .replace_tasks(TaskList::Completed, &self.completed_tasks),
+        );
+        log_store_error(self.store.replace_projects(&self.projects));
+        log_store_error(self.store.replace_labels(&self.labels));
+        if let Some(sync_token) = response.sync_token {
+            log_store_error(self.store.set_sync_token(Some(&sync_token)));
+            self.sync_token = Some(sync_token);
+        }
+        self.clamp_sidebar_selection();
+        self.reload_tasks();
+    }
+
+    /// Show that an API call failed, asking for a new token if Todoist
+    /// rejected the current one.
+    pub fn report_error(&mut self, error: &ApiError) {
+        self.sync_status = SyncStatus::from_error(error);
+        if self.sync_status == SyncStatus::Unauthorized && self.input_mode == InputMode::Normal {
+            self.input_mode = InputMode::EnterToken;
+            self.input_buffer.clear();
+        }
+    }
+
+    /// Load the user's saved filter views into the application state
+    pub fn load_saved_views(&mut self, saved_views: Vec<SavedView>) {
+        self.saved_views = saved_views;
+        self.clamp_sidebar_selection();
+    }
+
+    /// Load the tasks of a label or filter view, unless the user has since
+    /// moved to another view.
+    pub fn load_view_tasks(&mut self, view: &View, tasks: Vec<Task>) {
+        if self.view != *view {
+            return;
+        }
+        self.detect_conflicts(&tasks);
+        self.keeping_selection(|state| {
+            state.view_tasks = tasks;
+            state.reapply_pending_changes();
+        });
+    }
+
+    /// Load a project's tasks and sections, unless the user has since moved
+    /// to another view.
+    pub fn load_project_tasks(
+        &mut self,
+        project_id: &str,
+        tasks: Vec<Task>,
+        mut sections: Vec<Section>,
+    ) {
+        if self.view != View::Project(project_id.to_string()) {
+            return;
+        }
+        sections.sort_by_key(|s| s.section_order);
+        self.sections = sections;
+        self.detect_conflicts(&tasks);
+        self.keeping_selection(|state| {
+            state.view_tasks = tasks;
+            state.reapply_pending_changes();
+        });
+    }
+
+    /// The project currently shown, if the view is a project.
+    pub fn current_project(&self) -> Option<&Project> {
+        match &self.view {
+            View::Project(id) => self.projects.iter().find(|p| p.id == *id),
+            View::Today | View::Label(_) | View::Filter(_) => None,
+        }
+    }
+
+    /// The saved view for the filter currently shown, if it has been saved.
+    pub fn current_saved_view(&self) -> Option<&SavedView> {
+        match &self.view {
+            View::Filter(query) => self.saved_views.iter().find(|v| v.query == *query),
+            _ => None,
+        }
+    }
+
+    /// Whether a task belongs in the project or label view currently shown.
+    fn shows_in_view(&self, task: &Task) -> bool {
+        match &self.view {
+            // Filter queries can't be evaluated locally; the task shows up
+            // on the next refresh if it matches.
+            View::Today | View::Filter(_) => false,
+            View::Project(id) => task.project_id == *id,
+            View::Label(name) => task.labels.contains(name),
+        }
+    }
+
+    /// How deeply a project is nested under parent projects.
+    pub fn project_depth(&self, project: &Project) -> usize {
+        let mut depth = 0;
+        let mut parent_id = project.parent_id.as_deref();
+        while let Some(id) = parent_id {
+            // Guard against cycles in malformed data.
+            if depth > self.projects.len() {
+                break;
+            }
+            depth += 1;
+            parent_id = self
+                .projects
+                .iter()
+                .find(|p| p.id == id)
+                .and_then(|p| p.parent_id.as_deref());
+        }
+        depth
+    }
+
+    /// Open and focus the sidebar, or collapse it if it already has focus.
+    pub fn toggle_sidebar(&mut self) {
+        if self.sidebar.focused {
+            self.sidebar.open = false;
+            self.sidebar.focused = false;
+        } else {
+            self.sidebar.open = true;
+            self.sidebar.focused = true;
+        }
+    }
+
+    /// The views listed in the sidebar, in display order.
+    pub fn sidebar_views(&self) -> Vec<View> {
+        let mut entries = vec![View::Today];
+        entries.extend(
+            self.saved_views
+                .iter()
+                .map(|v| View::Filter(v.query.clone())),
+        );
+        entries.extend(self.projects.iter().map(|p| View::Project(p.id.clone())));
+        entries.extend(self.labels.iter().map(|l| View::Label(l.name.clone())));
+        entries
+    }
+
+    fn clamp_sidebar_selection(&mut self) {
+        let last = self.sidebar_views().len() - 1;
+        self.sidebar.selected = self.sidebar.selected.min(last);
+    }
+
+    /// Switch to the view highlighted in the sidebar, returning it if its
+    /// tasks now need fetching.
+    pub fn open_sidebar_selection(&mut self) -> Option<View> {
+        self.sidebar.focused = false;
+        let view = self.sidebar_views().get(self.sidebar.selected)?.clone();
+        self.switch_view(view)
+    }
+
+    /// Show `view` in the main pane, returning it if its tasks now need
+    /// fetching.
+    pub fn switch_view(&mut self, view: View) -> Option<View> {
+        if view == self.view {
+            return None;
+        }
+        self.view = view;
+        self.selected_index = 0;
+        self.view_tasks.clear();
+        self.sections.clear();
+        match &self.view {
+            View::Today => None,
+            view => Some(view.clone()),
+        }
+    }
+
+    /// Every loaded task, across all views. A task can appear more than once.
+    fn all_tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks
+            .iter()
+            .chain(self.completed_tasks.iter())
+            .chain(self.view_tasks.iter())
+    }
+
+    fn find_task(&self, task_id: &str) -> Option<&Task> {
+        self.all_tasks().find(|t| t.id == task_id)
+    }
+
+    /// Returns the number of selectable rows in the current view.
     pub fn unified_today_count(&self) -> usize {
-        self.today_tasks().len()
+        match self.view {
+            View::Today => self.unified_tasks().len(),
+            View::Project(_) => self.project_rows().len(),
+            View::Label(_) | View::Filter(_) => self.flat_view_tasks().len(),
+        }
+    }
+
+    /// An ID for each selectable row of the current view: the task's, or the
+    /// section's for a project section header.
+    fn row_ids(&self) -> Vec<String> {
+        match self.view {
+            View::Today => self.unified_tasks().iter().map(|t| t.id.clone()).collect(),
+            View::Project(_) => self
+                .project_rows()
+                .iter()
+                .map(|row| match row {
+                    ProjectRow::Section { section, .. } => format!("section:{}", section.id),
+                    ProjectRow::Task(task) => task.id.clone(),
+                })
+                .collect(),
+            View::Label(_) | View::Filter(_) => self
+                .flat_view_tasks()
+                .iter()
+                .map(|t| t.id.clone())
+                .collect(),
+        }
+    }
+
+    /// Run `reload`, then select the row that was selected before it. If that
+    /// row went away, select the nearest one that is still there, looking
+    /// first at those that came after it.
+    fn keeping_selection(&mut self, reload: impl FnOnce(&mut Self)) {
+        let before = self.row_ids();
+        let selected = self.selected_index;
+        reload(self);
+        let after = self.row_ids();
+        let nearest = (0..before.len()).find_map(|distance| {
+            [
+                selected.checked_add(distance),
+                selected.checked_sub(distance),
+            ]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| before.get(i))
+            .find_map(|id| after.iter().position(|a| a == id))
+        });
+        if let Some(index) = nearest {
+            self.selected_index = index;
+        }
+        self.clamp_selection();
+    }
+
+    /// Returns the tasks of a label or filter view, active ones first.
+    pub fn flat_view_tasks(&self) -> Vec<&Task> {
+        let mut tasks: Vec<&Task> = self.view_tasks.iter().collect();
+        tasks.sort_by_key(|task| task.is_completed);
+        self.arrange_subtasks(tasks)
     }
 
-    /// Toggle a task by its ID
+    /// Returns the selectable tasks of the Today view in display order:
+    /// overdue then today.
+    pub fn unified_tasks(&self) -> Vec<&Task> {
+        let mut unified = self.tasks_overdue();
+        unified.extend(self.today_tasks());
+        unified
+    }
+
+    /// Returns the current project's rows: tasks without a section first,
+    /// then each section's header followed by its tasks unless it is folded.
+    /// Active tasks come before completed ones within each group.
+    pub fn project_rows(&self) -> Vec<ProjectRow<'_>> {
+        let in_section = |section_id: Option<&str>| {
+            let mut tasks: Vec<&Task> = self
+                .view_tasks
+                .iter()
+                .filter(|t| {
+                    // Tasks in a section we don't know about go in the top group.
+                    let task_section = t
+                        .section_id
+                        .as_deref()
+                        .filter(|id| self.sections.iter().any(|s| s.id == *id));
+                    task_section == section_id
+                })
+                .collect();
+            tasks.sort_by_key(|task| task.is_completed);
+            self.arrange_subtasks(tasks)
+        };
+
+        let mut rows: Vec<ProjectRow> =
+            in_section(None).into_iter().map(ProjectRow::Task).collect();
+        for section in &self.sections {
+            let tasks = in_section(Some(&section.id));
+            let folded = self.folded_sections.contains(&section.id);
+            rows.push(ProjectRow::Section {
+                section,
+                task_count: tasks.len(),
+                folded,
+            });
+            if !folded {
+                rows.extend(tasks.into_iter().map(ProjectRow::Task));
+            }
+        }
+        rows
+    }
+
+    /// Fold or unfold the section under the cursor, or the one holding the
+    /// selected task. The cursor lands on the section's header.
+    pub fn toggle_selected_section(&mut self) {
+        let rows = self.project_rows();
+        let section_id = match rows.get(self.selected_index) {
+            Some(ProjectRow::Section { section, .. }) => section.id.clone(),
+            Some(ProjectRow::Task(task)) => match &task.section_id {
+                Some(id) => id.clone(),
+                None => return,
+            },
+            None => return,
+        };
+        let header = rows.iter().position(
+            |row| matches!(row, ProjectRow::Section { section, .. } if section.id == section_id),
+        );
+        let Some(header) = header else {
+            return;
+        };
+
+        if !self.folded_sections.remove(&section_id) {
+            self.folded_sections.insert(section_id);
+        }
+        self.selected_index = header;
+    }
+
+    /// Toggle a task by its ID, recording the change for sync and undo.
+    ///
+    /// Completing a task also completes its open subtasks, as Todoist does,
+    /// so the whole subtree comes back with a single undo.
     pub fn toggle_task_by_id(&mut self, selected_id: &str) {
-        // Toggle in the active tasks list if found.
-        if let Some(task) = self.tasks.iter_mut().find(|t| t.id == selected_id) {
-            task.is_completed = !task.is_completed;
-        } else if let Some(task) = self
-            .completed_tasks
+        let Some(task) = self.find_task(selected_id) else {
+            return;
+        };
+        let task_id = task.id.clone();
+        if task.is_completed {
+            self.record_change(Change::Reopen { task_id });
+            return;
+        }
+        let mut changes: Vec<Change> = self
+            .open_subtask_ids(&task_id)
+            .into_iter()
+            .rev()
+            .map(|task_id| Change::Complete { task_id })
+            .collect();
+        changes.push(Change::Complete { task_id });
+        self.record_changes(changes);
+    }
+
+    /// Toggle the selected task, asking first if that would also complete
+    /// open subtasks.
+    pub fn toggle_selected_task(&mut self) {
+        let Some(task_id) = self.selected_task_id() else {
+            return;
+        };
+        let completing = self.find_task(&task_id).is_some_and(|t| !t.is_completed);
+        if completing && !self.open_subtask_ids(&task_id).is_empty() {
+            self.input_mode = InputMode::ConfirmComplete(task_id);
+        } else {
+            self.toggle_task_by_id(&task_id);
+        }
+    }
+
+    /// IDs of every open task below `task_id` in the subtask tree, parents
+    /// before their children.
+    pub fn open_subtask_ids(&self, task_id: &str) -> Vec<String> {
+        self.subtree_ids(task_id, |task| !task.is_completed)
+    }
+
+    /// IDs of every task below `task_id` in the subtask tree, open or
+    /// completed, parents before their children.
+    pub fn subtask_ids(&self, task_id: &str) -> Vec<String> {
+        self.subtree_ids(task_id, |_| true)
+    }
+
+    /// IDs of the tasks below `task_id` in the subtask tree, following only
+    /// those that pass `include`.
+    fn subtree_ids(&self, task_id: &str, include: impl Fn(&Task) -> bool) -> Vec<String> {
+        let mut ids: Vec<String> = Vec::new();
+        let mut frontier = vec![task_id.to_string()];
+        while let Some(parent_id) = frontier.pop() {
+            for task in self.all_tasks() {
+                let is_child = task.parent_id.as_deref() == Some(parent_id.as_str());
+                // The same task can be loaded in more than one list, and
+                // malformed data could contain a cycle.
+                if is_child && include(task) && task.id != task_id && !ids.contains(&task.id) {
+                    ids.push(task.id.clone());
+                    frontier.push(task.id.clone());
+                }
+            }
+        }
+        ids
+    }
+
+    /// Whether any loaded task is a subtask of `task_id`.
+    pub fn has_subtasks(&self, task_id: &str) -> bool {
+        self.all_tasks()
+            .any(|t| t.parent_id.as_deref() == Some(task_id))
+    }
+
+    /// How many of a task's ancestors appear in `shown`, i.e. how far to
+    /// indent it within that list.
+    pub fn subtask_depth(&self, task: &Task, shown: &[&Task]) -> usize {
+        let mut depth = 0;
+        let mut parent_id = task.parent_id.as_deref();
+        while let Some(id) = parent_id {
+            let Some(parent) = shown.iter().find(|t| t.id == id) else {
+                break;
+            };
+            // Guard against cycles in malformed data.
+            if depth > shown.len() {
+                break;
+            }
+            depth += 1;
+            parent_id = parent.parent_id.as_deref();
+        }
+        depth
+    }
+
+    /// Fold or unfold the subtasks of the selected task. Returns false if the
+    /// selected task has no subtasks.
+    pub fn toggle_selected_subtasks(&mut self) -> bool {
+        let Some(task_id) = self.selected_task_id() else {
+            return false;
+        };
+        if !self.has_subtasks(&task_id) {
+            return false;
+        }
+        if !self.folded_tasks.remove(&task_id) {
+            self.folded_tasks.insert(task_id);
+        }
+        self.clamp_selection();
+        true
+    }
+
+    /// Order tasks as a tree: each task is followed by its subtasks (by
+    /// `child_order`), recursively. Tasks whose parent isn't in the list keep
+    /// their relative order as roots. Subtasks of folded tasks are left out.
+    fn arrange_subtasks<'a>(&self, tasks: Vec<&'a Task>) -> Vec<&'a Task> {
+        let is_root = |task: &&Task| {
+            task.parent_id
+                .as_deref()
+                .is_none_or(|id| !tasks.iter().any(|t| t.id == id))
+        };
+        let mut stack: Vec<&Task> = tasks.iter().copied().filter(is_root).rev().collect();
+        let mut arranged: Vec<&Task> = Vec::with_capacity(tasks.len());
+        while let Some(task) = stack.pop() {
+            if arranged.iter().any(|t| t.id == task.id) {
+                continue;
+            }
+            arranged.push(task);
+            if self.folded_tasks.contains(&task.id) {
+                continue;
+            }
+            let mut children: Vec<&Task> = tasks
+                .iter()
+                .copied()
+                .filter(|t| t.parent_id.as_deref() == Some(task.id.as_str()))
+                .collect();
+            children.sort_by_key(|t| t.child_order);
+            stack.extend(children.into_iter().rev());
+        }
+        arranged
+    }
+
+    /// Returns the ID of the currently selected task, if the cursor is on one.
+    pub fn selected_task_id(&self) -> Option<String> {
+        match self.view {
+            View::Today => self
+                .unified_tasks()
+                .get(self.selected_index)
+                .map(|t| t.id.clone()),
+            View::Project(_) => match self.project_rows().get(self.selected_index) {
+                Some(ProjectRow::Task(task)) => Some(task.id.clone()),
+                _ => None,
+            },
+            View::Label(_) | View::Filter(_) => self
+                .flat_view_tasks()
+                .get(self.selected_index)
+                .map(|t| t.id.clone()),
+        }
+    }
+
+    /// Ask to move every active overdue task to `days` days from today.
+    /// Only the Today view lists overdue tasks, so nothing happens elsewhere.
+    pub fn confirm_reschedule_overdue(&mut self, days: i64) {
+        if self.view == View::Today && self.overdue_count() > 0 {
+            self.input_mode = InputMode::ConfirmReschedule(days);
+        }
+    }
+
+    /// How many active tasks are overdue.
+    pub fn overdue_count(&self) -> usize {
+        self.tasks_overdue()
+            .into_iter()
+            .filter(|t| !t.is_completed)
+            .count()
+    }
+
+    /// Move every active overdue task to `days` days from today, as a single
+    /// undoable step. Times of day and recurrences are kept.
+    pub fn reschedule_overdue(&mut self, days: i64) {
+        let target = Local::now().date_naive() + chrono::Duration::days(days);
+        let changes: Vec<Change> = self
+            .tasks_overdue()
+            .into_iter()
+            .filter(|t| !t.is_completed)
+            .filter_map(|t| {
+                let due = t.due.as_ref()?;
+                Some(Change::Edit {
+                    task_id: t.id.clone(),
+                    update: rescheduled_due(due, target),
+                    previous: full_due(due),
+                })
+            })
+            .collect();
+        self.record_changes(changes);
+    }
+
+    /// Apply a change the user just made, queue it for sync and add it to the
+    /// undo history.
+    pub fn record_change(&mut self, change: Change) {
+        self.record_changes(vec![change]);
+    }
+
+    /// Like `record_change`, but for several changes that undo as one step.
+    pub fn record_changes(&mut self, changes: Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+        for change in &changes {
+            self.queue_change(change.clone());
+        }
+        self.undo_stack.push(changes);
+        if self.undo_stack.len() > MAX_UNDO_HISTORY {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+    }
+
+    /// Revert the most recent step. Returns `false` if there was nothing to undo.
+    ///
+    /// A change still in its grace period is simply dropped from the queue;
+    /// one that already synced has its inverse queued instead.
+    pub fn undo(&mut self) -> bool {
+        let Some(changes) = self.undo_stack.pop() else {
+            return false;
+        };
+        for inverse in changes.iter().rev().filter_map(Change::inverse) {
+            self.queue_change(inverse);
+        }
+        self.redo_stack.push(changes);
+        true
+    }
+
+    /// Re-apply the most recently undone step. Returns `false` if there was
+    /// nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let Some(changes) = self.redo_stack.pop() else {
+            return false;
+        };
+        for change in &changes {
+            self.queue_change(change.clone());
+        }
+        self.undo_stack.push(changes);
+        true
+    }
+
+    /// Record a change in the store, apply it locally and queue it for
+    /// delayed sync.
+    ///
+    /// A change that exactly reverses a pending one (e.g. completing then
+    /// reopening a task) removes it instead, so nothing is sent at all.
+    pub fn queue_change(&mut self, change: Change) {
+        let inverse = change.inverse();
+        if let Some(pos) = self
+            .pending_changes
+            .iter()
+            .rposition(|p| Some(&p.change) == inverse.as_ref())
+        {
+            let cancelled = self.pending_changes.remove(pos);
+            log_store_error(self.store.record_done(cancelled.key));
+            self.settle_task_base(&cancelled.change);
+        } else {
+            if let Change::Edit { task_id, .. } = &change {
+                self.record_task_base(task_id);
+            }
+            let key = Uuid::new_v4();
+            log_store_error(self.store.record_queued(key, &change));
+            // New tasks skip the grace period: there is nothing to undo yet,
+            // and the sooner they exist on the server the sooner they get a real ID.
+            let queued_at = match change {
+                Change::Create { .. } => Instant::now()
+                    .checked_sub(SYNC_GRACE_PERIOD)
+                    .unwrap_or_else(Instant::now),
+                _ => Instant::now(),
+            };
+            self.pending_changes.push(PendingChange {
+                key,
+                change: change.clone(),
+                queued_at,
+            });
+        }
+        self.apply_change(&change);
+    }
+
+    /// Open the edit form for the selected task.
+    pub fn start_editing_selected(&mut self) {
+        let Some(selected_id) = self.selected_task_id() else {
+            return;
+        };
+        let Some(task) = self.find_task(&selected_id) else {
+            return;
+        };
+        self.edit_form = Some(EditForm::new(task));
+        self.input_mode = InputMode::EditTask;
+    }
+
+    /// Close the edit form, recording any changes made in it.
+    pub fn finish_editing(&mut self, save: bool) {
+        self.input_mode = InputMode::Normal;
+        let Some(form) = self.edit_form.take() else {
+            return;
+        };
+        if !save {
+            return;
+        }
+        if let Some(change) = form.to_change() {
+            self.record_change(change);
+        }
+    }
+
+    /// Delete a task, keeping a tombstone so it can be undone.
+    ///
+    /// Its subtasks go with it, as on Todoist, each with a tombstone of its
+    /// own so the whole subtree comes back with a single undo. They are
+    /// deleted first, so every delete still finds its task on the server.
+    pub fn delete_task(&mut self, task_id: &str) {
+        if self.find_task(task_id).is_none() {
+            return;
+        }
+        let changes: Vec<Change> = self
+            .subtask_ids(task_id)
+            .into_iter()
+            .rev()
+            .chain([task_id.to_string()])
+            .filter_map(|id| self.find_task(&id).cloned())
+            .map(|task| Change::Delete {
+                task: Box::new(task),
+            })
+            .collect();
+        self.record_changes(changes);
+    }
+
+    /// Record new content and description for a task, e.g. after editing it
+    /// in an external editor. Only the fields that differ from `original`,
+    /// the task as it was when the edit began, are changed; anything else
+    /// that changed the task in the meantime is left alone.
+    pub fn edit_task_text(&mut self, original: &Task, content: String, description: String) {
+        if self.find_task(&original.id).is_none() {
+            return;
+        }
+        let mut update = TaskUpdate::default();
+        let mut previous = TaskUpdate::default();
+        if content != original.content {
+            previous.content = Some(original.content.clone());
+            update.content = Some(content);
+        }
+        if description != original.description {
+            previous.description = Some(original.description.clone());
+            update.description = Some(description);
+        }
+        if update != TaskUpdate::default() {
+            self.record_change(Change::Edit {
+                task_id: original.id.clone(),
+                update,
+                previous,
+            });
+        }
+    }
+
+    /// Switch to `InputMode::ExternalEditor` for the selected task, returning
+    /// the task to open in the editor.
+    pub fn start_external_edit(&mut self) -> Option<Task> {
+        let task = self
+            .selected_task_id()
+            .and_then(|id| self.find_task(&id).cloned())?;
+        self.input_mode = InputMode::ExternalEditor(Box::new(task.clone()));
+        Some(task)
+    }
+
+    /// Leave `InputMode::ExternalEditor`, recording the content and
+    /// description the editor returned, if any.
+    pub fn finish_external_edit(&mut self, edited: Option<(String, String)>) {
+        let original = match &self.input_mode {
+            InputMode::ExternalEditor(task) => task.clone(),
+            _ => return,
+        };
+        self.input_mode = InputMode::Normal;
+        if let Some((content, description)) = edited {
+            self.edit_task_text(&original, content, description);
+        }
+    }
+
+    /// Add a task from quick-add text, showing it straight away under a
+    /// temporary ID until the server responds.
+    pub fn add_task(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        self.queue_change(Change::Create {
+            temp_id: format!("tmp-{}", Uuid::new_v4()),
+            text: text.to_string(),
+        });
+    }
+
+    /// Run a `:` command, returning a view whose tasks now need fetching.
+    ///
+    /// - `filter <query>` (or `f <query>`) shows the tasks matching a Todoist
+    ///   filter query
+    /// - `save <name>` keeps the filter shown under a name in the sidebar
+    /// - `unsave` removes the shown filter from the sidebar
+    /// - `token` asks for a new API token
+    pub fn run_command(&mut self, command: &str) -> Option<View> {
+        let command = command.trim();
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        match name {
+            "filter" | "f" if !argument.is_empty() => {
+                self.switch_view(View::Filter(argument.to_string()))
+            }
+            "save" if !argument.is_empty() => {
+                let View::Filter(query) = &self.view else {
+                    self.sync_status = SyncStatus::Error("Only filters can be saved".to_string());
+                    return None;
+                };
+                let view = SavedView {
+                    name: argument.to_string(),
+                    query: query.clone(),
+                };
+                self.saved_views
+                    .retain(|v| v.name != view.name && v.query != view.query);
+                self.saved_views.push(view);
+                self.persist_saved_views();
+                None
+            }
+            "unsave" => {
+                if let View::Filter(query) = &self.view {
+                    let query = query.clone();
+                    self.saved_views.retain(|v| v.query != query);
+                    self.persist_saved_views();
+                    self.clamp_sidebar_selection();
+                }
+                None
+            }
+            "token" => {
+                self.input_mode = InputMode::EnterToken;
+                None
+            }
+            "" => None,
+            _ => {
+                self.sync_status = SyncStatus::Error(format!("Unknown command: {}", command));
+                None
+            }
+        }
+    }
+
+    fn persist_saved_views(&mut self) {
+        if let Err(e) = views::save(&self.saved_views) {
+            log::error!("Failed to save views: {}", e);
+            self.sync_status = SyncStatus::Error(format!("Saving views: {}", e));
+        }
+    }
+
+    /// Swap an optimistically created task for the one the server returned,
+    /// and re-point every queued or historical change at its real ID.
+    pub fn resolve_created_task(&mut self, temp_id: &str, task: Task) {
+        let real_id = task.id.clone();
+        if let Some(slot) = self.view_tasks.iter_mut().find(|t| t.id == temp_id) {
+            *slot = task.clone();
+        } else if self.shows_in_view(&task) {
+            self.view_tasks.push(task.clone());
+        }
+        let (list, tasks, others) = match task.is_completed {
+            true => (
+                TaskList::Completed,
+                &mut self.completed_tasks,
+                &mut self.tasks,
+            ),
+            false => (TaskList::Active, &mut self.tasks, &mut self.completed_tasks),
+        };
+        log_store_error(self.store.upsert_task(list, &task));
+        others.retain(|t| t.id != temp_id);
+        match tasks.iter_mut().find(|t| t.id == temp_id) {
+            Some(slot) => *slot = task,
+            None => tasks.push(task),
+        }
+
+        for subtask in self
+            .tasks
             .iter_mut()
-            .find(|t| t.id == selected_id)
+            .chain(self.completed_tasks.iter_mut())
+            .chain(self.view_tasks.iter_mut())
+            .filter(|t| t.parent_id.as_deref() == Some(temp_id))
         {
-            task.is_completed = !task.is_completed;
+            subtask.parent_id = Some(real_id.clone());
+        }
+        for pending in self.in_flight.iter_mut().chain(&mut self.pending_changes) {
+            pending.change.replace_task_id(temp_id, &real_id);
+        }
+        for step in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+            for change in step {
+                change.replace_task_id(temp_id, &real_id);
+            }
+        }
+        log_store_error(self.store.record_id_mapped(temp_id, &real_id));
+        self.reapply_pending_changes();
+    }
+
+    /// Queue changes recovered from the store, ready to be flushed at once.
+    fn restore_pending_changes(&mut self, changes: Vec<(Uuid, Change)>) {
+        let queued_at = Instant::now()
+            .checked_sub(SYNC_GRACE_PERIOD)
+            .unwrap_or_else(Instant::now);
+        self.pending_changes
+            .extend(changes.into_iter().map(|(key, change)| PendingChange {
+                key,
+                change,
+                queued_at,
+            }));
+        self.reapply_pending_changes();
+    }
+
+    /// Put changes that could not be sent back at the front of the queue,
+    /// keeping their original order.
+    pub fn requeue_changes(&mut self, changes: Vec<PendingChange>) {
+        self.in_flight
+            .retain(|p| !changes.iter().any(|c| c.key == p.key));
+        self.pending_changes.splice(0..0, changes);
+    }
+
+    /// Remove and return every pending change whose grace period has elapsed.
+    /// Edits held back by a conflict stay queued.
+    pub fn take_due_changes(&mut self, now: Instant) -> Vec<PendingChange> {
+        let held = self.conflicted_task_ids();
+        let (due, waiting): (Vec<_>, Vec<_>) = self.pending_changes.drain(..).partition(|p| {
+            now.duration_since(p.queued_at) >= SYNC_GRACE_PERIOD && !is_held(&p.change, &held)
+        });
+        self.pending_changes = waiting;
+        self.in_flight.extend(due.iter().cloned());
+        due
+    }
+
+    /// Remove and return every pending change regardless of age, except
+    /// edits held back by a conflict.
+    pub fn take_all_changes(&mut self) -> Vec<PendingChange> {
+        let held = self.conflicted_task_ids();
+        let (all, waiting): (Vec<_>, Vec<_>) = self
+            .pending_changes
+            .drain(..)
+            .partition(|p| !is_held(&p.change, &held));
+        self.pending_changes = waiting;
+        self.in_flight.extend(all.iter().cloned());
+        all
+    }
+
+    /// Time left until the oldest pending change is flushed.
+    pub fn time_until_next_flush(&self) -> Option<Duration> {
+        self.pending_changes
+            .iter()
+            .map(|p| p.queued_at)
+            .min()
+            .map(|oldest| SYNC_GRACE_PERIOD.saturating_sub(oldest.elapsed()))
+    }
+
+    /// Apply a change to the local task lists.
+    pub fn apply_change(&mut self, change: &Change) {
+        match change {
+            Change::Complete { task_id } => self.set_completed(task_id, true),
+            Change::Reopen { task_id } => self.set_completed(task_id, false),
+            Change::Edit {
+                task_id, update, ..
+            } => self.apply_update(task_id, update),
+            Change::Reorder { task_ids, .. } => self.apply_order(task_ids),
+            Change::Create { temp_id, text } => {
+                if !self.tasks.iter().any(|t| t.id == *temp_id) {
+                    self.tasks.push(quick_add_preview(temp_id, text));
+                }
+            }
+            Change::Delete { task } => {
+                self.tasks.retain(|t| t.id != task.id);
+                self.completed_tasks.retain(|t| t.id != task.id);
+                self.view_tasks.retain(|t| t.id != task.id);
+            }
+            Change::Restore { task } => {
+                if self.find_task(&task.id).is_none() {
+                    if self.shows_in_view(task) {
+                        self.view_tasks.push((**task).clone());
+                    }
+                    if task.is_completed {
+                        self.completed_tasks.push((**task).clone());
+                    } else {
+                        self.tasks.push((**task).clone());
+                    }
+                }
+            }
         }
+        self.clamp_selection();
     }
 
-    /// Move selection up within the unified today list.
+    /// Keep the selection inside the list after tasks disappear.
+    fn clamp_selection(&mut self) {
+        let count = self.unified_today_count();
+        if self.selected_index >= count {
+            self.selected_index = count.saturating_sub(1);
+        }
+    }
+
+    /// Record that a change reached Todoist.
+    pub fn mark_synced(&mut self, pending: &PendingChange) {
+        self.in_flight.retain(|p| p.key != pending.key);
+        log_store_error(self.store.apply_synced_change(&pending.change));
+        log_store_error(self.store.record_done(pending.key));
+        self.settle_task_base(&pending.change);
+    }
+
+    /// Undo the local effect of a change Todoist rejected, and forget it so it
+    /// can't be undone a second time.
+    pub fn rollback_change(&mut self, pending: &PendingChange) {
+        self.in_flight.retain(|p| p.key != pending.key);
+        log_store_error(self.store.record_done(pending.key));
+        self.settle_task_base(&pending.change);
+        match &pending.change {
+            Change::Create { temp_id, .. } => self.tasks.retain(|t| t.id != *temp_id),
+            change => {
+                if let Some(inverse) = change.inverse() {
+                    self.apply_change(&inverse);
+                }
+            }
+        }
+        if let Some(pos) = self
+            .undo_stack
+            .iter()
+            .rposition(|step| step.contains(&pending.change))
+        {
+            self.undo_stack[pos].retain(|c| *c != pending.change);
+            if self.undo_stack[pos].is_empty() {
+                self.undo_stack.remove(pos);
+            }
+        }
+    }
+
+    /// Keep the current copy of a task as the base its queued edits are
+    /// compared against, unless earlier edits already set one. Tasks the
+    /// server hasn't created yet have nothing to conflict with.
+    fn record_task_base(&mut self, task_id: &str) {
+        let not_created = self
+            .pending_changes
+            .iter()
+            .any(|p| p.change.created_task_id() == Some(task_id));
+        if self.task_bases.contains_key(task_id) || not_created {
+            return;
+        }
+        if let Some(task) = self.find_task(task_id).cloned() {
+            log_store_error(self.store.record_task_base(&task));
+            self.task_bases.insert(task.id.clone(), task);
+        }
+    }
+
+    /// Forget a task's base, and any conflict over it, once `change` was the
+    /// last edit to it still queued.
+    fn settle_task_base(&mut self, change: &Change) {
+        let Change::Edit { task_id, .. } = change else {
+            return;
+        };
+        if self.queued_update(task_id).is_some() {
+            return;
+        }
+        if self.task_bases.remove(task_id).is_some() {
+            log_store_error(self.store.forget_task_base(task_id));
+        }
+        self.conflicts.retain(|c| c.task_id != *task_id);
+    }
+
+    /// What the queued edits to a task set, combined; `None` if there are none.
+    fn queued_update(&self, task_id: &str) -> Option<TaskUpdate> {
+        let updates: Vec<&TaskUpdate> = self
+            .pending_changes
+            .iter()
+            .filter_map(|p| match &p.change {
+                Change::Edit {
+                    task_id: id,
+                    update,
+                    ..
+                } if id == task_id => Some(update),
+                _ => None,
+            })
+            .collect();
+        (!updates.is_empty()).then(|| combine_updates(updates))
+    }
+
+    /// Compare freshly fetched server copies of tasks with the bases of their
+    /// queued edits, recording a conflict for each that changed the same
+    /// fields differently. Opens the resolution dialog for the first new one
+    /// while nothing else is being typed.
+    fn detect_conflicts(&mut self, server_tasks: &[Task]) {
+        let mut found_new = false;
+        for theirs in server_tasks {
+            let Some(base) = self.task_bases.get(&theirs.id) else {
+                continue;
+            };
+            let Some(mine) = self.queued_update(&theirs.id) else {
+                continue;
+            };
+            let existing = self.conflicts.iter().position(|c| c.task_id == theirs.id);
+            match (Conflict::detect(base, theirs, &mine), existing) {
+                (Some(conflict), Some(i)) => {
+                    if self.conflicts[i].fields != conflict.fields {
+                        self.conflicts[i] = conflict;
+                    }
+                }
+                (Some(conflict), None) => {
+                    log::info!("Task {} was also changed on the server", theirs.id);
+                    self.conflicts.push(conflict);
+                    found_new = true;
+                }
+                (None, Some(i)) => {
+                    self.conflicts.remove(i);
+                }
+                (None, None) => {}
+            }
+        }
+        if self.conflicts.is_empty() && self.input_mode == InputMode::ResolveConflict {
+            self.input_mode = InputMode::Normal;
+        }
+        if found_new && self.input_mode == InputMode::Normal {
+            self.input_mode = InputMode::ResolveConflict;
+        }
+    }
+
+    fn conflicted_task_ids(&self) -> HashSet<String> {
+        self.conflicts.iter().map(|c| c.task_id.clone()).collect()
+    }
+
+    /// Open the resolution dialog for the first unresolved conflict.
+    pub fn start_resolving_conflicts(&mut self) {
+        if !self.conflicts.is_empty() {
+            self.input_mode = InputMode::ResolveConflict;
+        }
+    }
+
+    /// Resolve the first conflict. `keep_mine` picks a side for every field;
+    /// `None` uses the choice made for each field in the dialog.
+    ///
+    /// Fields given the server's value are dropped from the queued edits (and
+    /// the undo history), and the server's copy becomes the base, so the
+    /// remaining edits sync over it.
+    pub fn resolve_conflict(&mut self, keep_mine: Option<bool>) {
+        if self.conflicts.is_empty() {
+            return;
+        }
+        let mut conflict = self.conflicts.remove(0);
+        if let Some(keep_mine) = keep_mine {
+            for field in &mut conflict.fields {
+                field.keep_mine = keep_mine;
+            }
+        }
+        let task_id = conflict.task_id.clone();
+        let theirs_fields = conflict.fields_kept_theirs();
+        let narrow = |change: &mut Change| {
+            if let Change::Edit {
+                task_id: id,
+                update,
+                previous,
+            } = change
+            {
+                if *id == task_id {
+                    for field in &theirs_fields {
+                        field.clear(update);
+                        field.clear(previous);
+                    }
+                }
+            }
+        };
+
+        let mut kept = Vec::new();
+        for mut pending in std::mem::take(&mut self.pending_changes) {
+            narrow(&mut pending.change);
+            match &pending.change {
+                Change::Edit { update, .. } if *update == TaskUpdate::default() => {
+                    log_store_error(self.store.record_done(pending.key));
+                }
+                change => {
+                    log_store_error(self.store.record_updated(pending.key, change));
+                    kept.push(pending);
+                }
+            }
+        }
+        self.pending_changes = kept;
+        for step in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+            step.iter_mut().for_each(narrow);
+            step.retain(
+                |c| !matches!(c, Change::Edit { update, .. } if *update == TaskUpdate::default()),
+            );
+        }
+        self.undo_stack.retain(|step| !step.is_empty());
+        self.redo_stack.retain(|step| !step.is_empty());
+
+        for task in self.matching_tasks_mut(&task_id) {
+            for field in &theirs_fields {
+                field.copy(&conflict.theirs, task);
+            }
+        }
+        log_store_error(self.store.record_task_base(&conflict.theirs));
+        self.task_bases.insert(task_id.clone(), conflict.theirs);
+        if self.queued_update(&task_id).is_none() {
+            self.task_bases.remove(&task_id);
+            log_store_error(self.store.forget_task_base(&task_id));
+        }
+
+        if self.conflicts.is_empty() {
+            self.input_mode = InputMode::Normal;
+        }
+    }
+
+    /// Re-apply pending changes, and those being pushed, on top of freshly
+    /// loaded server data.
+    fn reapply_pending_changes(&mut self) {
+        let changes: Vec<Change> = self
+            .in_flight
+            .iter()
+            .chain(&self.pending_changes)
+            .map(|p| p.change.clone())
+            .collect();
+        for change in &changes {
+            self.apply_change(change);
+        }
+    }
+
+    /// Every loaded copy of a task; it may be in both the today and project lists.
+    fn matching_tasks_mut<'a>(
+        &'a mut self,
+        task_id: &'a str,
+    ) -> impl Iterator<Item = &'a mut Task> {
+        self.tasks
+            .iter_mut()
+            .chain(self.completed_tasks.iter_mut())
+            .chain(self.view_tasks.iter_mut())
+            .filter(move |t| t.id == task_id)
+    }
+
+    fn set_completed(&mut self, task_id: &str, completed: bool) {
+        for task in self.matching_tasks_mut(task_id) {
+            task.is_completed = completed;
+        }
+    }
+
+    fn apply_update(&mut self, task_id: &str, update: &TaskUpdate) {
+        for task in self.matching_tasks_mut(task_id) {
+            update_task_fields(task, update);
+        }
+    }
+
+    /// Rearrange the given tasks into the given relative order, keeping the
+    /// slots they occupy in `self.tasks`.
+    fn apply_order(&mut self, task_ids: &[String]) {
+        let slots: Vec<usize> = self
+            .tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| task_ids.contains(&t.id))
+            .map(|(i, _)| i)
+            .collect();
+        let ordered: Vec<Task> = task_ids
+            .iter()
+            .filter_map(|id| self.tasks.iter().find(|t| &t.id == id).cloned())
+            .collect();
+        if ordered.len() != slots.len() {
+            return;
+        }
+        for (slot, task) in slots.into_iter().zip(ordered) {
+            self.tasks[slot] = task;
+        }
+    }
+
+    /// Move selection up within the focused list.
     pub fn move_up(&mut self) {
-        if self.selected_index > 0 {
+        if self.sidebar.focused {
+            self.sidebar.selected = self.sidebar.selected.saturating_sub(1);
+        } else if self.selected_index > 0 {
             self.selected_index -= 1;
         }
     }
 
-    /// Move selection down within the unified today list.
+    /// Move selection down within the focused list.
     pub fn move_down(&mut self) {
-        if self.selected_index + 1 < self.unified_today_count() {
+        if self.sidebar.focused {
+            if self.sidebar.selected + 1 < self.sidebar_views().len() {
+                self.sidebar.selected += 1;
+            }
+        } else if self.selected_index + 1 < self.unified_today_count() {
             self.selected_index += 1;
         }
     }
@@ -103,55 +1799,43 @@ impl AppState {
         }
     }
 
+    /// Returns tasks whose due date is before today.
+    pub fn tasks_overdue(&self) -> Vec<&Task> {
+        let today = Local::now().naive_local().date();
+        let mut overdue: Vec<&Task> = self
+            .tasks
+            .iter()
+            .filter(|task| due_date(task).is_some_and(|d| d < today))
+            .collect();
+        overdue.sort_by_key(|task| task.is_completed);
+        self.arrange_subtasks(overdue)
+    }
+
     /// Returns tasks whose due date equals today.
     pub fn tasks_due_today(&self) -> Vec<&Task> {
         let today = Local::now().naive_local().date();
-        self.tasks
+        let due_today = self
+            .tasks
             .iter()
-            .filter(|task| {
-                if let Some(due) = &task.due {
-                    // If due.date is in "YYYY-MM-DD" format:
-                    if due.date.len() == 10 {
-                        if let Ok(d) = NaiveDate::parse_from_str(&due.date, "%Y-%m-%d") {
-                            return d == today;
-                        }
-                    } else if due.date.contains('T') {
-                        // Try to parse as a date-time from RFC3339.
-                        if let Ok(dt) = DateTime::parse_from_rfc3339(&due.date) {
-                            return dt.with_timezone(&Local).date_naive() == today;
-                        }
-                    }
-                }
-                false
-            })
-            .collect()
+            .filter(|task| due_date(task) == Some(today))
+            .collect();
+        self.arrange_subtasks(due_today)
     }
 
-    /// Returns tasks that are NOT due today.
+    /// Returns tasks due after today.
     /// Tasks with no due date are considered upcoming.
     pub fn tasks_upcoming(&self) -> Vec<&Task> {
         let today = Local::now().naive_local().date();
-        self.tasks
+        let upcoming = self
+            .tasks
             .iter()
-            .filter(|task| {
-                if let Some(due) = &task.due {
-                    if due.date.len() == 10 {
-                        if let Ok(d) = NaiveDate::parse_from_str(&due.date, "%Y-%m-%d") {
-                            return d > today;
-                        }
-                    } else if due.date.contains('T') {
-                        if let Ok(dt) = DateTime::parse_from_rfc3339(&due.date) {
-                            return dt.with_timezone(&Local).date_naive() > today;
-                        }
-                    }
-                    // If parsing fails, do not display in "today"
-                    false
-                } else {
-                    // No due date → upcoming
-                    true
-                }
+            .filter(|task| match &task.due {
+                Some(_) => due_date(task).is_some_and(|d| d > today),
+                // No due date → upcoming
+                None => true,
             })
-            .collect()
+            .collect();
+        self.arrange_subtasks(upcoming)
     }
 
     pub fn today_tasks(&self) -> Vec<&Task> {
@@ -159,6 +1843,169 @@ impl AppState {
         combined.extend(self.completed_tasks.iter());
         // Optionally sort so that active tasks appear first
         combined.sort_by_key(|task| task.is_completed);
-        combined
+        self.arrange_subtasks(combined)
+    }
+}
+
+/// Parses a task's due date, whether it is a plain date or a date-time.
+pub fn due_date(task: &Task) -> Option<NaiveDate> {
+    let due = task.due.as_ref()?;
+    // If due.date is in "YYYY-MM-DD" format:
+    if due.date.len() == 10 {
+        return NaiveDate::parse_from_str(&due.date, "%Y-%m-%d").ok();
+    }
+    // Try to parse as a date-time from RFC3339.
+    if let Ok(dt) = DateTime::parse_from_rfc3339(&due.date) {
+        return Some(dt.with_timezone(&Local).date_naive());
+    }
+    // Floating date-times ("YYYY-MM-DDTHH:MM:SS") carry no offset; use the date part.
+    due.date
+        .get(..10)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+}
+
+/// Builds a local stand-in for a quick-added task.
+///
+/// Only the easy parts of the syntax are handled here (`p1`–`p4`, `@label`,
+/// and `today` or `tomorrow` as the date); `#project` and other tokens are
+/// just dropped from the title. The server's parsed task replaces this one as soon
+/// as it arrives.
+pub fn quick_add_preview(temp_id: &str, text: &str) -> Task {
+    let today = Local::now().date_naive();
+    let mut priority = 1;
+    let mut date = None;
+    let mut labels = Vec::new();
+    let mut words = Vec::new();
+    for word in text.split_whitespace() {
+        match word.to_lowercase().as_str() {
+            // Todoist's p1 (most urgent) is priority 4 in the API.
+            "p1" => priority = 4,
+            "p2" => priority = 3,
+            "p3" => priority = 2,
+            "p4" => priority = 1,
+            "today" | "tod" => date = Some(today),
+            "tomorrow" | "tom" => date = Some(today + chrono::Duration::days(1)),
+            w if w.starts_with('@') => labels.push(word[1..].to_string()),
+            w if w.starts_with('#') => {}
+            _ => words.push(word),
+        }
+    }
+
+    Task {
+        id: temp_id.to_string(),
+        content: words.join(" "),
+        labels,
+        priority,
+        due: date.map(|d| {
+            let date = d.format("%Y-%m-%d").to_string();
+            Due {
+                string: date.clone(),
+                date,
+                is_recurring: false,
+                datetime: None,
+                timezone: None,
+            }
+        }),
+        ..Task::default()
+    }
+}
+
+/// The value read from the store, or the empty default if that failed.
+fn stored<T: Default>(result: rusqlite::Result<T>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        log::error!("Failed to read {} from store: {}", what, e);
+        T::default()
+    })
+}
+
+fn log_store_error(result: rusqlite::Result<()>) {
+    if let Err(e) = result {
+        log::error!("Failed to update store: {}", e);
+    }
+}
+
+/// Whether `change` is an edit held back by a conflict over its task.
+fn is_held(change: &Change, conflicted: &HashSet<String>) -> bool {
+    matches!(change, Change::Edit { task_id, .. } if conflicted.contains(task_id))
+}
+
+/// Replaces the task with the same ID in `tasks`, or appends it.
+fn upsert_task(tasks: &mut Vec<Task>, task: Task) {
+    match tasks.iter_mut().find(|t| t.id == task.id) {
+        Some(slot) => *slot = task,
+        None => tasks.push(task),
+    }
+}
+
+/// Applies the `Some` fields of an update to a task.
+pub fn update_task_fields(task: &mut Task, update: &TaskUpdate) {
+    if let Some(content) = &update.content {
+        task.content = content.clone();
+    }
+    if let Some(description) = &update.description {
+        task.description = description.clone();
+    }
+    if let Some(priority) = update.priority {
+        task.priority = priority;
+    }
+    if let Some(due_string) = &update.due_string {
+        // Without a date alongside, the server works out the actual date;
+        // refreshed on the next load.
+        if due_string.eq_ignore_ascii_case(NO_DUE_DATE) {
+            task.due = None;
+        } else if let Some(due) = task.due.as_mut() {
+            due.string = due_string.clone();
+        }
+    }
+    if let Some(due_date) = &update.due_date {
+        let date: String = due_date.chars().take(10).collect();
+        let datetime = (due_date.len() > 10).then(|| due_date.clone());
+        match task.due.as_mut() {
+            // As on Todoist, a date without a string ends any recurrence.
+            Some(due) if update.due_string.is_none() => {
+                due.string = date.clone();
+                due.date = date;
+                due.datetime = datetime;
+                due.is_recurring = false;
+            }
+            Some(due) => {
+                due.date = date;
+                due.datetime = datetime;
+            }
+            None => {
+                task.due = Some(Due {
+                    string: update.due_string.clone().unwrap_or_else(|| date.clone()),
+                    date,
+                    is_recurring: false,
+                    datetime,
+                    timezone: None,
+                })
+            }
+        }
+    }
+}
+
+/// `due` moved to `date`, at the same time of day if it has one. A
+/// recurring due keeps its due string, so it goes on recurring from there.
+fn rescheduled_due(due: &Due, date: NaiveDate) -> TaskUpdate {
+    // The time, and the `Z` of a fixed timezone, follow the date.
+    let time = due
+        .datetime
+        .as_deref()
+        .and_then(|datetime| datetime.get(10..))
+        .unwrap_or_default();
+    TaskUpdate {
+        due_date: Some(format!("{}{}", date.format("%Y-%m-%d"), time)),
+        due_string: due.is_recurring.then(|| due.string.clone()),
+        ..TaskUpdate::default()
+    }
+}
+
+/// The whole of `due` as an update, e.g. to put it back on undo.
+fn full_due(due: &Due) -> TaskUpdate {
+    TaskUpdate {
+        due_string: Some(due.string.clone()),
+        due_date: Some(due.datetime.clone().unwrap_or_else(|| due.date.clone())),
+        ..TaskUpdate::default()
     }
 }
@@ -0,0 +1,467 @@
+//! Embedded SQLite store
+//!
+//! Handles:
+//! - Keeping the last known tasks, projects and labels, plus sync metadata,
+//!   under the XDG data dir so the UI can render before the network answers
+//! - Merging fetched tasks into what is stored, so tasks keep their local
+//!   order across refreshes
+//! - Applying changes once Todoist has accepted them
+//! - Recording queued changes before they are applied, and recovering the
+//!   unsynced ones after a crash or an offline quit
+//!
+//! The store holds what the server is known to have. `AppState` reads its
+//! task lists from it and layers the still-pending changes on top. Each
+//! pending change carries an idempotency key that is also sent to Todoist, so
+//! replaying one whose request already went through never applies it twice.
+
+use crate::api::{Label, Project, Task};
+use crate::state::{update_task_fields, Change};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tasks (
+        id TEXT PRIMARY KEY,
+        list TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        -- Set while a refresh of the list hasn't seen the task yet.
+        stale INTEGER NOT NULL DEFAULT 0,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS projects (
+        id TEXT PRIMARY KEY,
+        position INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS labels (
+        id TEXT PRIMARY KEY,
+        position INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pending_changes (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        key TEXT NOT NULL UNIQUE,
+        change TEXT NOT NULL
+    );
+    -- The server copy of each task with queued edits, as it was when the
+    -- first of them was made.
+    CREATE TABLE IF NOT EXISTS task_bases (
+        task_id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+const SYNC_TOKEN_KEY: &str = "sync_token";
+const REFRESHED_AT_KEY: &str = "refreshed_at";
+
+/// Which of the Today view's lists a stored task belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskList {
+    /// Active tasks due today or overdue.
+    Active,
+    /// Tasks completed today.
+    Completed,
+}
+
+impl TaskList {
+    fn as_str(self) -> &'static str {
+        match self {
+            TaskList::Active => "active",
+            TaskList::Completed => "completed",
+        }
+    }
+}
+
+/// A handle on the SQLite database. Clones share the same connection.
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// Directory holding TUIdoist's data, e.g. `~/.local/share/tuidoist`. Not
+/// the cache dir: the store holds the only copy of unsynced changes.
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("tuidoist"))
+}
+
+fn to_json<T: Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+}
+
+fn from_json<T: DeserializeOwned>(json: &str) -> rusqlite::Result<T> {
+    serde_json::from_str(json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+impl Store {
+    /// Open the store called `name` in the default data directory.
+    pub fn open_default(name: &str) -> rusqlite::Result<Self> {
+        let dir = data_dir().ok_or(rusqlite::Error::InvalidPath("No data directory".into()))?;
+        std::fs::create_dir_all(&dir).map_err(|_| rusqlite::Error::InvalidPath(dir.clone()))?;
+        Self::open(&dir.join(format!("{}.db", name)))
+    }
+
+    /// Open (or create) the store at `path`.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // Readers don't block the writer, and every commit survives a crash.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        log::debug!("Opened store at {}", path.display());
+        Self::with_connection(conn)
+    }
+
+    /// A store that lives only as long as the process, e.g. for tests.
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// The tasks in `list`, in their local order.
+    pub fn tasks(&self, list: TaskList) -> rusqlite::Result<Vec<Task>> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT data FROM tasks WHERE list = ?1 ORDER BY position")?;
+        let rows = statement.query_map([list.as_str()], |row| row.get::<_, String>(0))?;
+        rows.map(|json| from_json(&json?)).collect()
+    }
+
+    /// Start refreshing `list`: tasks not merged back in before
+    /// `finish_task_refresh` are dropped then.
+    pub fn begin_task_refresh(&self, list: TaskList) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE tasks SET stale = 1 WHERE list = ?1",
+            [list.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Add or update fetched tasks in `list`. Tasks already in the list keep
+    /// their place; new ones go at the end.
+    pub fn merge_tasks(&self, list: TaskList, tasks: &[Task]) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for task in tasks {
+            upsert_task(&tx, list, task)?;
+        }
+        tx.commit()
+    }
+
+    /// Drop the tasks in `list` that the refresh didn't see.
+    pub fn finish_task_refresh(&self, list: TaskList) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "DELETE FROM tasks WHERE list = ?1 AND stale = 1",
+            [list.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Make `list` hold exactly `tasks`, keeping the local order of those
+    /// already in it.
+    pub fn replace_tasks(&self, list: TaskList, tasks: &[Task]) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE tasks SET stale = 1 WHERE list = ?1",
+            [list.as_str()],
+        )?;
+        for task in tasks {
+            upsert_task(&tx, list, task)?;
+        }
+        tx.execute(
+            "DELETE FROM tasks WHERE list = ?1 AND stale = 1",
+            [list.as_str()],
+        )?;
+        tx.commit()
+    }
+
+    /// Add or update a single task in `list`.
+    pub fn upsert_task(&self, list: TaskList, task: &Task) -> rusqlite::Result<()> {
+        upsert_task(&self.conn(), list, task)
+    }
+
+    /// Apply a change Todoist has accepted to the stored tasks. Created and
+    /// restored tasks are added with `upsert_task` once their real ID is
+    /// known.
+    pub fn apply_synced_change(&self, change: &Change) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        match change {
+            Change::Complete { task_id } => move_task(&tx, task_id, TaskList::Completed, true)?,
+            Change::Reopen { task_id } => move_task(&tx, task_id, TaskList::Active, false)?,
+            Change::Edit {
+                task_id, update, ..
+            } => {
+                if let Some(mut task) = find_task(&tx, task_id)? {
+                    update_task_fields(&mut task, update);
+                    tx.execute(
+                        "UPDATE tasks SET data = ?2 WHERE id = ?1",
+                        params![task_id, to_json(&task)?],
+                    )?;
+                }
+            }
+            Change::Reorder { task_ids, .. } => {
+                // The tasks swap places among the positions they already hold.
+                let mut positions = Vec::new();
+                for task_id in task_ids {
+                    let position: Option<i64> = tx
+                        .query_row(
+                            "SELECT position FROM tasks WHERE id = ?1",
+                            [task_id],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    positions.extend(position);
+                }
+                if positions.len() == task_ids.len() {
+                    positions.sort_unstable();
+                    for (task_id, position) in task_ids.iter().zip(positions) {
+                        tx.execute(
+                            "UPDATE tasks SET position = ?2 WHERE id = ?1",
+                            params![task_id, position],
+                        )?;
+                    }
+                }
+            }
+            Change::Delete { task } => {
+                tx.execute("DELETE FROM tasks WHERE id = ?1", [&task.id])?;
+            }
+            Change::Create { .. } | Change::Restore { .. } => {}
+        }
+        tx.commit()
+    }
+
+    pub fn projects(&self) -> rusqlite::Result<Vec<Project>> {
+        self.load_all("projects")
+    }
+
+    pub fn replace_projects(&self, projects: &[Project]) -> rusqlite::Result<()> {
+        self.replace_all("projects", projects.iter().map(|p| (&p.id, p)))
+    }
+
+    pub fn labels(&self) -> rusqlite::Result<Vec<Label>> {
+        self.load_all("labels")
+    }
+
+    pub fn replace_labels(&self, labels: &[Label]) -> rusqlite::Result<()> {
+        self.replace_all("labels", labels.iter().map(|l| (&l.id, l)))
+    }
+
+    fn load_all<T: DeserializeOwned>(&self, table: &str) -> rusqlite::Result<Vec<T>> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare(&format!("SELECT data FROM {} ORDER BY position", table))?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|json| from_json(&json?)).collect()
+    }
+
+    fn replace_all<'a, T: Serialize + 'a>(
+        &self,
+        table: &str,
+        rows: impl Iterator<Item = (&'a String, &'a T)>,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(&format!("DELETE FROM {}", table), [])?;
+        for (position, (id, value)) in rows.enumerate() {
+            tx.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (id, position, data) VALUES (?1, ?2, ?3)",
+                    table
+                ),
+                params![id, position as i64, to_json(value)?],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Where the last incremental sync left off.
+    pub fn sync_token(&self) -> rusqlite::Result<Option<String>> {
+        self.meta(SYNC_TOKEN_KEY)
+    }
+
+    pub fn set_sync_token(&self, sync_token: Option<&str>) -> rusqlite::Result<()> {
+        self.set_meta(SYNC_TOKEN_KEY, sync_token)
+    }
+
+    /// When the stored tasks were last refreshed from the server.
+    pub fn refreshed_at(&self) -> rusqlite::Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .meta(REFRESHED_AT_KEY)?
+            .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+            .map(|at| at.with_timezone(&Utc)))
+    }
+
+    /// Record that the stored tasks were just refreshed, returning the time.
+    pub fn mark_refreshed(&self) -> rusqlite::Result<DateTime<Utc>> {
+        let now = Utc::now();
+        self.set_meta(REFRESHED_AT_KEY, Some(&now.to_rfc3339()))?;
+        Ok(now)
+    }
+
+    fn meta(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        self.conn()
+            .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    fn set_meta(&self, key: &str, value: Option<&str>) -> rusqlite::Result<()> {
+        let conn = self.conn();
+        match value {
+            Some(value) => conn.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?,
+            None => conn.execute("DELETE FROM meta WHERE key = ?1", [key])?,
+        };
+        Ok(())
+    }
+
+    /// Every change that was queued but never marked done, in order.
+    pub fn pending_changes(&self) -> rusqlite::Result<Vec<(Uuid, Change)>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT key, change FROM pending_changes ORDER BY seq")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut pending = Vec::new();
+        for row in rows {
+            let (key, change) = row?;
+            match (Uuid::parse_str(&key), from_json(&change)) {
+                (Ok(key), Ok(change)) => pending.push((key, change)),
+                _ => log::warn!("Skipping unreadable pending change {}", key),
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Durably record a change before it is applied.
+    pub fn record_queued(&self, key: Uuid, change: &Change) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO pending_changes (key, change) VALUES (?1, ?2)",
+            params![key.to_string(), to_json(change)?],
+        )?;
+        Ok(())
+    }
+
+    /// Replace a pending change, keeping its place in the queue.
+    pub fn record_updated(&self, key: Uuid, change: &Change) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE pending_changes SET change = ?2 WHERE key = ?1",
+            params![key.to_string(), to_json(change)?],
+        )?;
+        Ok(())
+    }
+
+    /// Record that pending changes referring to `temp_id` mean `real_id`.
+    pub fn record_id_mapped(&self, temp_id: &str, real_id: &str) -> rusqlite::Result<()> {
+        let pending = self.pending_changes()?;
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for (key, mut change) in pending {
+            let before = change.clone();
+            change.replace_task_id(temp_id, real_id);
+            if change != before {
+                tx.execute(
+                    "UPDATE pending_changes SET change = ?2 WHERE key = ?1",
+                    params![key.to_string(), to_json(&change)?],
+                )?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Record that a change no longer needs syncing.
+    pub fn record_done(&self, key: Uuid) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "DELETE FROM pending_changes WHERE key = ?1",
+            [key.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// The copies of tasks queued edits were made against.
+    pub fn task_bases(&self) -> rusqlite::Result<Vec<Task>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT data FROM task_bases")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|data| from_json(&data?)).collect()
+    }
+
+    /// Keep `task` as the copy queued edits to it were made against.
+    pub fn record_task_base(&self, task: &Task) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO task_bases (task_id, data) VALUES (?1, ?2)",
+            params![task.id, to_json(task)?],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the base copy of a task once no edits to it are queued.
+    pub fn forget_task_base(&self, task_id: &str) -> rusqlite::Result<()> {
+        self.conn()
+            .execute("DELETE FROM task_bases WHERE task_id = ?1", [task_id])?;
+        Ok(())
+    }
+}
+
+fn upsert_task(conn: &Connection, list: TaskList, task: &Task) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO tasks (id, list, position, stale, data)
+         VALUES (?1, ?2, (SELECT COALESCE(MAX(position), 0) + 1 FROM tasks), 0, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+             list = excluded.list,
+             position = CASE WHEN tasks.list = excluded.list
+                 THEN tasks.position ELSE excluded.position END,
+             stale = 0,
+             data = excluded.data",
+        params![task.id, list.as_str(), to_json(task)?],
+    )?;
+    Ok(())
+}
+
+fn find_task(conn: &Connection, task_id: &str) -> rusqlite::Result<Option<Task>> {
+    let json: Option<String> = conn
+        .query_row("SELECT data FROM tasks WHERE id = ?1", [task_id], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    json.map(|json| from_json(&json)).transpose()
+}
+
+fn move_task(
+    conn: &Connection,
+    task_id: &str,
+    list: TaskList,
+    completed: bool,
+) -> rusqlite::Result<()> {
+    if let Some(mut task) = find_task(conn, task_id)? {
+        task.is_completed = completed;
+        upsert_task(conn, list, &task)?;
+    }
+    Ok(())
+}
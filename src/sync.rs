@@ -0,0 +1,661 @@
+//! Background sync with the Todoist API
+//!
+//! Handles:
+//! - Refreshing task lists and merging what the server returns into the store
+//! - Refreshing periodically in the background, backing off while offline
+//! - Flushing queued changes once their grace period has elapsed
+//! - Pushing each change through the Todoist API, or batching them as Sync
+//!   API commands when incremental sync is on
+//! - Applying incremental Sync API deltas on refresh
+//! - Rolling back changes the API rejects
+
+use crate::api::{ApiError, SyncCommand, Task};
+use crate::backend::Backend;
+use crate::state::{AppState, Change, InputMode, PendingChange, SyncStatus, View, NO_DUE_DATE};
+use crate::store::TaskList;
+use chrono::Local;
+use futures::{Stream, TryStreamExt};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use uuid::Uuid;
+
+/// How often the background task checks for changes that are due.
+const FLUSH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait between replay attempts while Todoist is unreachable.
+const OFFLINE_RETRY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often to refresh in the background unless configured otherwise.
+pub const DEFAULT_AUTO_SYNC_INTERVAL: Duration = Duration::from_secs(120);
+
+/// The longest the background refresh backs off to while refreshes fail.
+const MAX_AUTO_SYNC_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// Fetch today's active and completed tasks, the projects and labels, and the
+/// open project or label view's tasks, and load them into the state.
+///
+/// What comes back is merged into the store; if the API can't be reached at
+/// all (even after the backend's retries) the state keeps whatever the store
+/// had and goes `Offline`.
+pub async fn refresh<B: Backend>(app_state: &Arc<Mutex<AppState>>, backend: &B) {
+    let view = {
+        let mut state = app_state.lock().await;
+        state.sync_status = SyncStatus::Syncing;
+        state.view.clone()
+    };
+    if backend.uses_incremental_sync() {
+        return refresh_incremental(app_state, backend, view).await;
+    }
+
+    // Task pages are loaded as they arrive, so a long list starts rendering
+    // before the last page is in.
+    let (active_result, completed_result, projects_result, labels_result) = tokio::join!(
+        stream_task_pages(app_state, backend.todays_task_pages(), TaskList::Active),
+        stream_task_pages(
+            app_state,
+            backend.todays_completed_task_pages(),
+            TaskList::Completed
+        ),
+        backend.get_projects(),
+        backend.get_labels(),
+    );
+
+    let mut state = app_state.lock().await;
+    match active_result {
+        Ok(()) => {
+            state.sync_status = SyncStatus::Online;
+        }
+        Err(e) => {
+            log::warn!("Refreshing tasks failed: {}", e);
+            state.report_error(&e);
+            return;
+        }
+    }
+    if let Err(e) = completed_result {
+        log::warn!("Refreshing completed tasks failed: {}", e);
+    }
+    match projects_result {
+        Ok(projects) => state.load_projects(projects),
+        Err(e) => log::warn!("Refreshing projects failed: {}", e),
+    }
+    match labels_result {
+        Ok(labels) => state.load_labels(labels),
+        Err(e) => log::warn!("Refreshing labels failed: {}", e),
+    }
+    state.mark_refreshed();
+    drop(state);
+
+    load_view(app_state, backend, &view).await;
+}
+
+/// Refresh through the Sync API, fetching only what changed since the last
+/// sync token. A full sync (the first one, or after the token expired) also
+/// fetches today's completed tasks, which the Sync API doesn't return.
+///
+/// The first refresh on a new day is a full sync too: a delta leaves out
+/// tasks that only became due today, and yesterday's completed tasks would
+/// stay on the Today list.
+async fn refresh_incremental<B: Backend>(
+    app_state: &Arc<Mutex<AppState>>,
+    backend: &B,
+    view: View,
+) {
+    let sync_token = {
+        let state = app_state.lock().await;
+        let today = Local::now().date_naive();
+        let refreshed_today = state
+            .refreshed_at
+            .is_some_and(|at| at.with_timezone(&Local).date_naive() == today);
+        match &state.sync_token {
+            Some(sync_token) if refreshed_today => sync_token.clone(),
+            _ => "*".to_string(),
+        }
+    };
+    let result = backend.sync(Some(&sync_token), &[]).await;
+
+    let full_sync = {
+        let mut state = app_state.lock().await;
+        match result {
+            Ok(response) => {
+                let full_sync = response.full_sync;
+                state.apply_sync_response(response);
+                state.sync_status = SyncStatus::Online;
+                full_sync
+            }
+            Err(e) => {
+                log::warn!("Syncing failed: {}", e);
+                state.report_error(&e);
+                return;
+            }
+        }
+    };
+
+    if full_sync {
+        if let Err(e) = stream_task_pages(
+            app_state,
+            backend.todays_completed_task_pages(),
+            TaskList::Completed,
+        )
+        .await
+        {
+            log::warn!("Refreshing completed tasks failed: {}", e);
+        }
+    }
+
+    app_state.lock().await.mark_refreshed();
+
+    // Deltas keep project and label views current, but filter queries can
+    // only be evaluated by the server.
+    if matches!(view, View::Filter(_)) {
+        load_view(app_state, backend, &view).await;
+    }
+}
+
+/// Merge pages of tasks into the stored `list` as they arrive. Tasks no page
+/// returned are dropped once the last one is in. Only a failure on the first
+/// page is reported; later failures keep the pages already merged, and the
+/// stored tasks not seen yet, and stop there.
+async fn stream_task_pages(
+    app_state: &Arc<Mutex<AppState>>,
+    pages: impl Stream<Item = Result<Vec<Task>, ApiError>>,
+    list: TaskList,
+) -> Result<(), ApiError> {
+    futures::pin_mut!(pages);
+    let mut first = true;
+    loop {
+        let page = match pages.try_next().await {
+            Ok(Some(page)) => page,
+            Ok(None) => {
+                app_state.lock().await.finish_task_refresh(list);
+                return Ok(());
+            }
+            Err(e) if first => return Err(e),
+            Err(e) => {
+                log::warn!("Stopped fetching further pages: {}", e);
+                return Ok(());
+            }
+        };
+        let mut state = app_state.lock().await;
+        state.load_task_page(list, page, first);
+        first = false;
+    }
+}
+
+/// Fetch the tasks of a project, label or filter view.
+pub async fn load_view<B: Backend>(app_state: &Arc<Mutex<AppState>>, backend: &B, view: &View) {
+    let result = match view {
+        View::Today => return,
+        View::Project(project_id) => return load_project(app_state, backend, project_id).await,
+        View::Label(label) => backend.get_label_tasks(label).await,
+        View::Filter(query) => backend.get_tasks_by_filter(query).await,
+    };
+    match result {
+        Ok(tasks) => {
+            let mut state = app_state.lock().await;
+            state.load_view_tasks(view, tasks);
+        }
+        Err(e) => {
+            log::warn!("Fetching view tasks failed: {}", e);
+            let mut state = app_state.lock().await;
+            state.report_error(&e);
+        }
+    }
+}
+
+/// Fetch a project's tasks and sections for the project view.
+async fn load_project<B: Backend>(app_state: &Arc<Mutex<AppState>>, backend: &B, project_id: &str) {
+    let result = tokio::try_join!(
+        backend.get_project_tasks(project_id),
+        backend.get_sections(project_id),
+    );
+    match result {
+        Ok((tasks, sections)) => {
+            let mut state = app_state.lock().await;
+            state.load_project_tasks(project_id, tasks, sections);
+        }
+        Err(e) => {
+            log::warn!("Fetching project tasks failed: {}", e);
+            let mut state = app_state.lock().await;
+            state.report_error(&e);
+        }
+    }
+}
+
+/// Spawn a background task that flushes pending changes as they come due.
+pub fn spawn_flush_loop<B: Backend>(
+    app_state: Arc<Mutex<AppState>>,
+    backend: Arc<B>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_CHECK_INTERVAL);
+        let mut last_push: Option<Instant> = None;
+        loop {
+            interval.tick().await;
+            let due = {
+                let mut state = app_state.lock().await;
+                // While offline, rate limited or locked out, only retry
+                // occasionally; a successful refresh flips the status back to
+                // online and replays straight away.
+                let waiting_to_retry =
+                    last_push.is_some_and(|t| t.elapsed() < OFFLINE_RETRY_INTERVAL);
+                if state.sync_status.is_waiting_to_retry() && waiting_to_retry {
+                    continue;
+                }
+                state.take_due_changes(Instant::now())
+            };
+            if !due.is_empty() {
+                last_push = Some(Instant::now());
+            }
+            push_changes(&app_state, backend.as_ref(), due).await;
+        }
+    })
+}
+
+/// Spawn a background task that refreshes every `interval`.
+///
+/// While refreshes fail (offline, rate limited, token rejected) the wait
+/// doubles after each one, up to `MAX_AUTO_SYNC_BACKOFF`. Meanwhile, unless
+/// the token was rejected, a cheap request checks every
+/// `OFFLINE_RETRY_INTERVAL` (or `interval`, if shorter) whether Todoist can
+/// be reached again. As soon as that or anything else gets through, such as
+/// a push or a manual refresh, it refreshes straight away. Nothing is
+/// refreshed while a prompt, the edit form or `$EDITOR` is open, so the
+/// lists don't shift under the user mid-edit.
+pub fn spawn_auto_sync_loop<B: Backend>(
+    app_state: Arc<Mutex<AppState>>,
+    backend: Arc<B>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(FLUSH_CHECK_INTERVAL.min(interval));
+        let mut next_sync = Instant::now() + interval;
+        let mut next_probe = Instant::now();
+        let mut failures = 0;
+        let mut was_offline = false;
+        loop {
+            ticks.tick().await;
+            let probe_every = {
+                let state = app_state.lock().await;
+                // Something else is talking to the server; see how it went
+                // on a later tick.
+                if state.sync_status == SyncStatus::Syncing {
+                    continue;
+                }
+                if was_offline && state.sync_status == SyncStatus::Online {
+                    log::info!("Connection is back; refreshing");
+                    next_sync = Instant::now();
+                }
+                was_offline = state.sync_status.is_waiting_to_retry();
+                // Covers `InputMode::ExternalEditor` too: the task being
+                // edited there mustn't change before the editor closes.
+                if state.input_mode != InputMode::Normal {
+                    continue;
+                }
+                probe_delay(&state.sync_status, interval)
+            };
+
+            if Instant::now() < next_sync {
+                match probe_every {
+                    Some(delay) if Instant::now() >= next_probe => {
+                        next_probe = Instant::now() + delay;
+                    }
+                    _ => continue,
+                }
+                if backend.get_labels().await.is_err() {
+                    continue;
+                }
+                log::info!("Todoist answered again; refreshing");
+            }
+
+            refresh(&app_state, backend.as_ref()).await;
+            let state = app_state.lock().await;
+            was_offline = state.sync_status.is_waiting_to_retry();
+            failures = if was_offline { failures + 1 } else { 0 };
+            next_sync = Instant::now() + auto_sync_delay(interval, failures);
+            next_probe =
+                Instant::now() + probe_delay(&state.sync_status, interval).unwrap_or_default();
+        }
+    })
+}
+
+/// How long to wait for the next background refresh after `failures` failed
+/// ones in a row.
+fn auto_sync_delay(interval: Duration, failures: u32) -> Duration {
+    interval
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_AUTO_SYNC_BACKOFF.max(interval))
+}
+
+/// How long to wait between checks whether Todoist can be reached again
+/// while `status` holds refreshes back, or `None` to not check at all: a
+/// rejected token stays rejected until the user enters a new one.
+fn probe_delay(status: &SyncStatus, interval: Duration) -> Option<Duration> {
+    let delay = OFFLINE_RETRY_INTERVAL.min(interval);
+    match status {
+        SyncStatus::Offline => Some(delay),
+        SyncStatus::RateLimited { retry_after } => Some(retry_after.unwrap_or_default().max(delay)),
+        _ => None,
+    }
+}
+
+/// Push every pending change immediately, regardless of its age.
+pub async fn flush_all<B: Backend>(app_state: &Arc<Mutex<AppState>>, backend: &B) {
+    let changes = {
+        let mut state = app_state.lock().await;
+        state.take_all_changes()
+    };
+    push_changes(app_state, backend, changes).await;
+}
+
+/// Push changes in order, rolling back any that Todoist rejects.
+///
+/// With incremental sync, consecutive changes go out together as one batch of
+/// Sync API commands; otherwise each is its own REST request. If a change
+/// fails for a reason that may clear up (no connection, rate limit, server
+/// error, rejected token), it and everything after it go back on the queue
+/// (and stay in the store) to be replayed later.
+async fn push_changes<B: Backend>(
+    app_state: &Arc<Mutex<AppState>>,
+    backend: &B,
+    changes: Vec<PendingChange>,
+) {
+    if changes.is_empty() {
+        return;
+    }
+    {
+        let mut state = app_state.lock().await;
+        state.sync_status = SyncStatus::Syncing;
+    }
+
+    let mut last_error = None;
+    let mut remaining = changes;
+    while !remaining.is_empty() {
+        let batch_len = if backend.uses_incremental_sync() {
+            batch_len(&remaining)
+        } else {
+            1
+        };
+        let batch: Vec<PendingChange> = remaining.drain(..batch_len).collect();
+        let result = push_batch(backend, &batch).await;
+
+        let mut state = app_state.lock().await;
+        let results = match result {
+            Ok(results) => results,
+            Err(e) => {
+                log::warn!("Keeping changes for later: {}", e);
+                let mut unsent = batch;
+                unsent.append(&mut remaining);
+                state.requeue_changes(unsent);
+                state.report_error(&e);
+                return;
+            }
+        };
+        let mut results = batch.into_iter().zip(results);
+        while let Some((pending, result)) = results.next() {
+            match result {
+                Err(error) if error.is_transient() => {
+                    log::warn!("Keeping changes for later: {}", error);
+                    let mut unsent = vec![pending];
+                    unsent.extend(results.map(|(pending, _)| pending));
+                    unsent.append(&mut remaining);
+                    state.requeue_changes(unsent);
+                    state.report_error(&error);
+                    return;
+                }
+                Ok(created) => {
+                    if let (Some(temp_id), Some(task)) = (pending.change.created_task_id(), created)
+                    {
+                        // Later changes may still use the temporary ID.
+                        for later in &mut remaining {
+                            later.change.replace_task_id(temp_id, &task.id);
+                        }
+                        state.resolve_created_task(temp_id, task);
+                    }
+                    state.mark_synced(&pending);
+                }
+                Err(error) => {
+                    log::warn!("Todoist rejected {:?}: {}", pending.change, error);
+                    state.rollback_change(&pending);
+                    last_error = Some(error);
+                }
+            }
+        }
+    }
+
+    let mut state = app_state.lock().await;
+    match last_error {
+        Some(e) => state.report_error(&e),
+        None => state.sync_status = SyncStatus::Online,
+    }
+}
+
+/// How many changes from the front of `changes` can go out as one batch of
+/// Sync API commands. Quick-add has no Sync API command, so a `Create` always
+/// goes alone through REST.
+fn batch_len(changes: &[PendingChange]) -> usize {
+    match changes
+        .iter()
+        .position(|p| matches!(p.change, Change::Create { .. }))
+    {
+        Some(0) => 1,
+        Some(n) => n,
+        None => changes.len(),
+    }
+}
+
+/// Send a batch of changes, returning each one's outcome in order. Fails as a
+/// whole only if the request may succeed when retried.
+async fn push_batch<B: Backend>(
+    backend: &B,
+    batch: &[PendingChange],
+) -> Result<Vec<Result<Option<Task>, ApiError>>, ApiError> {
+    if let [pending] = batch {
+        if !backend.uses_incremental_sync() || matches!(pending.change, Change::Create { .. }) {
+            return match push_change(backend, pending).await {
+                Err(e) if e.is_transient() => Err(e),
+                result => Ok(vec![result]),
+            };
+        }
+    }
+
+    // A restored task only has a temporary ID until the batch runs, so later
+    // commands in the same batch have to refer to it by that.
+    let mut changes: Vec<Change> = Vec::with_capacity(batch.len());
+    let mut commands: Vec<Vec<SyncCommand>> = Vec::with_capacity(batch.len());
+    let mut renamed: Vec<(String, String)> = Vec::new();
+    for pending in batch {
+        let mut change = pending.change.clone();
+        for (from, to) in &renamed {
+            change.replace_task_id(from, to);
+        }
+        let group = sync_commands(pending.key, &change);
+        if let (Change::Restore { task }, Some(temp_id)) = (&pending.change, &group[0].temp_id) {
+            renamed.push((task.id.clone(), temp_id.clone()));
+        }
+        changes.push(change);
+        commands.push(group);
+    }
+    let response = match backend.sync(None, &commands.concat()).await {
+        Ok(response) => response,
+        Err(e) if e.is_transient() => return Err(e),
+        Err(e) => {
+            return Ok(batch
+                .iter()
+                .map(|_| Err(ApiError::Command(e.to_string())))
+                .collect())
+        }
+    };
+    let results = changes
+        .iter()
+        .zip(&commands)
+        .map(|(change, group)| {
+            for command in group {
+                match response.sync_status.get(&command.uuid) {
+                    Some(status) if status == "ok" => {}
+                    Some(error) => return Err(ApiError::Command(error.to_string())),
+                    None => return Err(ApiError::Command("command was not run".to_string())),
+                }
+            }
+            let Change::Restore { task } = change else {
+                return Ok(None);
+            };
+            let real_id = group[0]
+                .temp_id
+                .as_ref()
+                .and_then(|temp_id| response.temp_id_mapping.get(temp_id))
+                .ok_or_else(|| ApiError::Decode("no ID for the restored task".to_string()))?;
+            // A subtask restored along with its parent names it by temp_id.
+            let parent_id = task.parent_id.as_ref().map(|parent_id| {
+                response
+                    .temp_id_mapping
+                    .get(parent_id)
+                    .unwrap_or(parent_id)
+                    .clone()
+            });
+            Ok(Some(Task {
+                id: real_id.clone(),
+                parent_id,
+                ..(**task).clone()
+            }))
+        })
+        .collect();
+    Ok(results)
+}
+
+/// The Sync API commands for a change, keyed by its idempotency key. Only a
+/// completed task's restore takes more than one: it is added, then closed.
+fn sync_commands(key: Uuid, change: &Change) -> Vec<SyncCommand> {
+    let command = sync_command(key, change);
+    match change {
+        Change::Restore { task } if task.is_completed => {
+            let close = SyncCommand {
+                kind: "item_close".to_string(),
+                uuid: Uuid::new_v5(&key, b"close").to_string(),
+                temp_id: None,
+                args: json!({ "id": command.temp_id }),
+            };
+            vec![command, close]
+        }
+        _ => vec![command],
+    }
+}
+
+/// The Sync API command for a change, keyed by its idempotency key.
+fn sync_command(key: Uuid, change: &Change) -> SyncCommand {
+    let command = |kind: &str, args: serde_json::Value| SyncCommand {
+        kind: kind.to_string(),
+        uuid: key.to_string(),
+        temp_id: None,
+        args,
+    };
+    match change {
+        Change::Complete { task_id } => command("item_close", json!({ "id": task_id })),
+        Change::Reopen { task_id } => command("item_uncomplete", json!({ "id": task_id })),
+        Change::Edit {
+            task_id, update, ..
+        } => {
+            let mut args = json!({ "id": task_id });
+            if let Some(content) = &update.content {
+                args["content"] = json!(content);
+            }
+            if let Some(description) = &update.description {
+                args["description"] = json!(description);
+            }
+            if let Some(priority) = update.priority {
+                args["priority"] = json!(priority);
+            }
+            if let Some(due_string) = &update.due_string {
+                args["due"] = if due_string == NO_DUE_DATE {
+                    serde_json::Value::Null
+                } else {
+                    json!({ "string": due_string })
+                };
+            }
+            if let Some(date) = &update.due_date {
+                // Sent with a date, the string is kept as it is, so a
+                // recurring task stays recurring.
+                args["due"] = match &update.due_string {
+                    Some(due_string) => json!({ "date": date, "string": due_string }),
+                    None => json!({ "date": date }),
+                };
+            }
+            command("item_update", args)
+        }
+        Change::Reorder { task_ids, .. } => {
+            let ids_to_orders: serde_json::Map<String, serde_json::Value> = task_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.clone(), json!(i + 1)))
+                .collect();
+            command(
+                "item_update_day_orders",
+                json!({ "ids_to_orders": ids_to_orders }),
+            )
+        }
+        // Batches never hold a Create (see `batch_len`): quick-add text has no
+        // Sync API equivalent, so this plain-content add is only a fallback.
+        Change::Create { temp_id, text } => SyncCommand {
+            temp_id: Some(temp_id.clone()),
+            ..command("item_add", json!({ "content": text }))
+        },
+        Change::Delete { task } => command("item_delete", json!({ "id": task.id })),
+        Change::Restore { task } => {
+            let mut args = json!({
+                "content": task.content,
+                "description": task.description,
+                "priority": task.priority,
+                "labels": task.labels,
+            });
+            if !task.project_id.is_empty() {
+                args["project_id"] = json!(task.project_id);
+            }
+            if let Some(section_id) = &task.section_id {
+                args["section_id"] = json!(section_id);
+            }
+            if let Some(parent_id) = &task.parent_id {
+                args["parent_id"] = json!(parent_id);
+            }
+            if let Some(due) = &task.due {
+                // The date as it was, with the string kept for a recurrence.
+                let date = due.datetime.as_ref().unwrap_or(&due.date);
+                args["due"] = json!({ "date": date, "string": due.string });
+            }
+            // Derived from the key, so a retried batch the server already
+            // ran still finds the restored task in `temp_id_mapping`.
+            SyncCommand {
+                temp_id: Some(format!("restore-{}", key)),
+                ..command("item_add", args)
+            }
+        }
+    }
+}
+
+/// Send a single change to the Todoist API, keyed by its idempotency key.
+///
+/// Returns the server's copy of the task for changes that create one.
+async fn push_change<B: Backend>(
+    backend: &B,
+    pending: &PendingChange,
+) -> Result<Option<Task>, ApiError> {
+    let request_id = pending.key.to_string();
+    match &pending.change {
+        Change::Complete { task_id } => backend.close_task(task_id, &request_id).await?,
+        Change::Reopen { task_id } => backend.reopen_task(task_id, &request_id).await?,
+        Change::Edit {
+            task_id, update, ..
+        } => backend.update_task(task_id, update, &request_id).await?,
+        Change::Reorder { task_ids, .. } => backend.reorder_tasks(task_ids, &request_id).await?,
+        Change::Create { text, .. } => {
+            return Ok(Some(backend.quick_add_task(text, &request_id).await?));
+        }
+        Change::Delete { task } => backend.delete_task(&task.id, &request_id).await?,
+        Change::Restore { task } => {
+            return Ok(Some(backend.create_task(task, &request_id).await?));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,926 @@
+//! End-to-end tests against the in-memory mock Todoist server and the local
+//! file backend
+//!
+//! Each test drives the real backend, sync and state code through fetching,
+//! rendering, toggling and pushing changes, without touching the network.
+
+use crate::api::{Due, Task, TaskUpdate, TodoistClient};
+use crate::backend::Backend;
+use crate::local::LocalBackend;
+use crate::mock_server::MockTodoist;
+use crate::state::{AppState, Change, InputMode, SyncStatus, View};
+use crate::store::Store;
+use crate::sync;
+use crate::ui::{ScrollOffsets, UI};
+use axum::http::StatusCode;
+use ratatui::backend::TestBackend;
+use ratatui::Terminal;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn task_due_today(id: &str, content: &str) -> Task {
+    let today = chrono::Local::now().date_naive().to_string();
+    Task {
+        id: id.to_string(),
+        content: content.to_string(),
+        due: Some(Due {
+            date: today,
+            is_recurring: false,
+            datetime: None,
+            string: "today".to_string(),
+            timezone: None,
+        }),
+        priority: 1,
+        ..Default::default()
+    }
+}
+
+fn todays_tasks() -> Vec<Task> {
+    vec![
+        task_due_today("1", "Buy milk"),
+        task_due_today("2", "Walk the dog"),
+        task_due_today("3", "Write report"),
+    ]
+}
+
+/// A mock server holding `tasks`, a client for it and app state that has
+/// already fetched them.
+async fn synced(tasks: Vec<Task>) -> (MockTodoist, TodoistClient, Arc<Mutex<AppState>>) {
+    let server = MockTodoist::start(tasks).await;
+    let client = server.client();
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    sync::refresh(&app_state, &client).await;
+    (server, client, app_state)
+}
+
+/// A local task file of its own for a test, starting out empty.
+fn local_task_file(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "tuidoist-tests-{}-{}.json",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A store file of its own for a test, starting out empty.
+fn store_file(name: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("tuidoist-tests-{}-{}.db", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Draw the whole UI for `state` and return the screen's text.
+fn render(state: &AppState) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+    terminal
+        .draw(|f| UI::render_ui(f, state, &mut ScrollOffsets::default()))
+        .unwrap();
+    terminal
+        .backend()
+        .buffer()
+        .content()
+        .iter()
+        .map(|cell| cell.symbol.as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn refresh_fetches_every_page_and_renders_todays_tasks() {
+    let server = MockTodoist::start(todays_tasks()).await;
+    let client = server.client().with_page_limit(2);
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+
+    sync::refresh(&app_state, &client).await;
+
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert_eq!(state.tasks.len(), 3);
+    let screen = render(&state);
+    for content in ["Buy milk", "Walk the dog", "Write report"] {
+        assert!(screen.contains(content), "{} missing from screen", content);
+    }
+}
+
+#[tokio::test]
+async fn completing_a_task_closes_it_on_the_server() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+
+    let task_id = {
+        let mut state = app_state.lock().await;
+        let task_id = state.selected_task_id().expect("a task is selected");
+        state.toggle_selected_task();
+        assert_eq!(state.pending_changes.len(), 1);
+        task_id
+    };
+    sync::flush_all(&app_state, &client).await;
+
+    assert!(server.task(&task_id).unwrap().is_completed);
+    assert!(server
+        .requests()
+        .contains(&format!("POST /tasks/{}/close", task_id)));
+
+    sync::refresh(&app_state, &client).await;
+    let state = app_state.lock().await;
+    assert!(state.pending_changes.is_empty());
+    assert!(!state.tasks.iter().any(|t| t.id == task_id));
+    assert!(state
+        .completed_tasks
+        .iter()
+        .any(|t| t.id == task_id && t.is_completed));
+}
+
+#[tokio::test]
+async fn a_refresh_during_a_push_keeps_the_change_being_pushed() {
+    for incremental in [false, true] {
+        let (server, client, app_state) = match incremental {
+            true => synced_incrementally(todays_tasks()).await,
+            false => synced(todays_tasks()).await,
+        };
+
+        // The flush has taken the change but not yet heard back.
+        let in_flight = {
+            let mut state = app_state.lock().await;
+            state.toggle_task_by_id("2");
+            state.take_all_changes()
+        };
+        sync::refresh(&app_state, &client).await;
+        let done = |state: &AppState| {
+            let mut loaded = state.tasks.iter().chain(&state.completed_tasks);
+            loaded.any(|t| t.id == "2" && t.is_completed)
+        };
+        assert!(done(&*app_state.lock().await));
+
+        app_state.lock().await.requeue_changes(in_flight);
+        sync::flush_all(&app_state, &client).await;
+        assert!(server.task("2").unwrap().is_completed);
+        let state = app_state.lock().await;
+        assert!(state.in_flight.is_empty());
+        assert!(done(&state));
+    }
+}
+
+#[tokio::test]
+async fn a_quick_added_task_takes_its_real_id_everywhere() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+    let temp_id = {
+        let mut state = app_state.lock().await;
+        state.add_task("Call the plumber today p2");
+        let task = state.tasks.iter().find(|t| t.content == "Call the plumber");
+        let temp_id = task.expect("shown before it syncs").id.clone();
+        state.toggle_task_by_id(&temp_id);
+        temp_id
+    };
+    // The add goes through; completing it is put off by a rate limit.
+    server.pass_next();
+    server.fail_next(StatusCode::TOO_MANY_REQUESTS, Some(3600));
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.requests().contains(&"POST /tasks/quick".to_string()));
+
+    {
+        let state = app_state.lock().await;
+        let real_id = "created-1";
+        assert_eq!(server.task(real_id).unwrap().content, "Call the plumber");
+        assert!(state.tasks.iter().all(|t| t.id != temp_id));
+        assert_eq!(state.pending_changes.len(), 1);
+        assert!(matches!(
+            &state.pending_changes[0].change,
+            Change::Complete { task_id } if task_id == real_id
+        ));
+        let history: Vec<&Change> = state.undo_stack.iter().flatten().collect();
+        assert!(matches!(
+            history[..],
+            [Change::Complete { task_id }] if task_id == real_id
+        ));
+    }
+
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.task("created-1").unwrap().is_completed);
+}
+
+#[tokio::test]
+async fn a_change_the_server_rejects_is_rolled_back() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+
+    {
+        let mut state = app_state.lock().await;
+        state.toggle_selected_task();
+    }
+    server.fail_next(StatusCode::NOT_FOUND, None);
+    sync::flush_all(&app_state, &client).await;
+
+    let state = app_state.lock().await;
+    assert!(state.tasks.iter().all(|t| !t.is_completed));
+    assert!(matches!(state.sync_status, SyncStatus::Error(_)));
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let server = MockTodoist::start(todays_tasks()).await;
+    let client = server.client();
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+
+    server.fail_next(StatusCode::SERVICE_UNAVAILABLE, None);
+    server.fail_next(StatusCode::TOO_MANY_REQUESTS, Some(0));
+    sync::refresh(&app_state, &client).await;
+
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert_eq!(state.tasks.len(), 3);
+}
+
+#[tokio::test]
+async fn a_rejected_token_asks_for_a_new_one() {
+    let server = MockTodoist::start(todays_tasks()).await;
+    let client = server.client();
+    client.set_api_token("revoked".to_string());
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+
+    sync::refresh(&app_state, &client).await;
+    {
+        let state = app_state.lock().await;
+        assert_eq!(state.sync_status, SyncStatus::Unauthorized);
+        assert_eq!(state.input_mode, InputMode::EnterToken);
+    }
+
+    client.set_api_token(crate::mock_server::MOCK_API_TOKEN.to_string());
+    sync::refresh(&app_state, &client).await;
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert_eq!(state.tasks.len(), 3);
+}
+
+/// Like `synced`, but talking to the mock server through the Sync API.
+async fn synced_incrementally(
+    tasks: Vec<Task>,
+) -> (MockTodoist, TodoistClient, Arc<Mutex<AppState>>) {
+    let server = MockTodoist::start(tasks).await;
+    let client = server.client().with_incremental_sync(true);
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    sync::refresh(&app_state, &client).await;
+    (server, client, app_state)
+}
+
+/// How many requests the mock server has had for "METHOD /path".
+fn count_requests(server: &MockTodoist, request: &str) -> usize {
+    server.requests().iter().filter(|r| *r == request).count()
+}
+
+#[tokio::test]
+async fn an_incremental_refresh_applies_only_what_changed() {
+    let (server, client, app_state) = synced_incrementally(todays_tasks()).await;
+    assert_eq!(app_state.lock().await.tasks.len(), 3);
+    let first_token = app_state.lock().await.sync_token.clone();
+    assert!(first_token.is_some());
+
+    server.edit_task(
+        "1",
+        &TaskUpdate {
+            content: Some("Buy oat milk".to_string()),
+            ..Default::default()
+        },
+    );
+    server.delete_task("2");
+    sync::refresh(&app_state, &client).await;
+
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    let contents: Vec<&str> = state.tasks.iter().map(|t| t.content.as_str()).collect();
+    assert_eq!(contents, vec!["Buy oat milk", "Write report"]);
+    assert_ne!(state.sync_token, first_token);
+    assert_eq!(count_requests(&server, "POST /sync"), 2);
+    // Completed tasks are only fetched along with a full sync.
+    assert_eq!(
+        count_requests(&server, "GET /tasks/completed/by_completion_date"),
+        1
+    );
+}
+
+#[tokio::test]
+async fn the_first_incremental_refresh_of_a_new_day_is_a_full_sync() {
+    let (server, client, app_state) = synced_incrementally(todays_tasks()).await;
+    app_state.lock().await.refreshed_at = Some(chrono::Utc::now() - chrono::Duration::days(1));
+    sync::refresh(&app_state, &client).await;
+
+    assert_eq!(
+        count_requests(&server, "GET /tasks/completed/by_completion_date"),
+        2,
+        "completed tasks fetched again"
+    );
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert_eq!(state.tasks.len(), 3);
+}
+
+#[tokio::test]
+async fn a_command_rejected_partway_through_a_batch_rolls_back_alone() {
+    let (server, client, app_state) = synced_incrementally(todays_tasks()).await;
+    {
+        let mut state = app_state.lock().await;
+        state.toggle_task_by_id("1");
+        let task = state.tasks.iter().find(|t| t.id == "2").cloned().unwrap();
+        state.edit_task_text(&task, "Walk the dog twice".to_string(), String::new());
+        state.toggle_task_by_id("3");
+    }
+    server.delete_task("2");
+    sync::flush_all(&app_state, &client).await;
+
+    assert_eq!(count_requests(&server, "POST /sync"), 2, "one batch");
+    assert!(server.task("1").unwrap().is_completed);
+    assert!(server.task("3").unwrap().is_completed);
+    let state = app_state.lock().await;
+    assert!(state.pending_changes.is_empty());
+    assert!(matches!(state.sync_status, SyncStatus::Error(_)));
+    let task = state.tasks.iter().find(|t| t.id == "2").unwrap();
+    assert_eq!(task.content, "Walk the dog");
+}
+
+#[tokio::test]
+async fn a_restore_through_the_sync_api_takes_the_id_it_was_mapped_to() {
+    let (server, client, app_state) = synced_incrementally(todays_tasks()).await;
+    app_state.lock().await.delete_task("1");
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.task("1").is_none());
+
+    {
+        let mut state = app_state.lock().await;
+        assert!(state.undo());
+        // Refers to the restored task by its old ID, in the same batch.
+        state.toggle_task_by_id("1");
+    }
+    sync::flush_all(&app_state, &client).await;
+
+    let restored = server.task("created-1").expect("restored on the server");
+    assert_eq!(restored.content, "Buy milk");
+    assert!(restored.is_completed);
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert!(state
+        .tasks
+        .iter()
+        .chain(state.completed_tasks.iter())
+        .all(|t| t.id != "1"));
+    assert!(state
+        .tasks
+        .iter()
+        .chain(state.completed_tasks.iter())
+        .any(|t| t.id == "created-1"));
+}
+
+#[tokio::test]
+async fn the_local_backend_keeps_changes_across_restarts() {
+    let path = local_task_file("restarts");
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+
+    app_state
+        .lock()
+        .await
+        .add_task("Water the plants today @home p1");
+    sync::flush_all(&app_state, &backend).await;
+    let task_id = {
+        let mut state = app_state.lock().await;
+        assert_eq!(state.sync_status, SyncStatus::Online);
+        let task = state.tasks.iter().find(|t| t.content == "Water the plants");
+        let task = task.expect("the added task is shown").clone();
+        assert_eq!(task.labels, vec!["home".to_string()]);
+        assert_eq!(task.priority, 4);
+        assert!(!task.id.starts_with("tmp-"));
+        state.toggle_selected_task();
+        task.id
+    };
+    sync::flush_all(&app_state, &backend).await;
+    drop(backend);
+
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    sync::refresh(&app_state, &backend).await;
+    let state = app_state.lock().await;
+    assert!(state.tasks.is_empty());
+    assert!(state
+        .completed_tasks
+        .iter()
+        .any(|t| t.id == task_id && t.is_completed));
+    assert!(render(&state).contains("Water the plants"));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn the_local_backend_answers_simple_filters() {
+    let path = local_task_file("filters");
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    for text in ["Call mum @phone p1", "Call the bank @phone", "Buy bread p1"] {
+        backend.quick_add_task(text, "").await.unwrap();
+    }
+
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    let view = View::Filter("p1 & @phone".to_string());
+    app_state.lock().await.switch_view(view.clone());
+    sync::load_view(&app_state, &backend, &view).await;
+    {
+        let state = app_state.lock().await;
+        let contents: Vec<&str> = state
+            .view_tasks
+            .iter()
+            .map(|t| t.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["Call mum"]);
+    }
+
+    let view = View::Filter("due before: monday".to_string());
+    app_state.lock().await.switch_view(view.clone());
+    sync::load_view(&app_state, &backend, &view).await;
+    let state = app_state.lock().await;
+    assert!(matches!(state.sync_status, SyncStatus::Error(_)));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn unsynced_changes_and_tasks_survive_a_restart() {
+    let server = MockTodoist::start(todays_tasks()).await;
+    let client = server.client();
+    let path = store_file("restart");
+    let app_state = Arc::new(Mutex::new(AppState::with_store(
+        Store::open(&path).unwrap(),
+    )));
+    sync::refresh(&app_state, &client).await;
+    let task_id = {
+        let mut state = app_state.lock().await;
+        let task_id = state.selected_task_id().expect("a task is selected");
+        state.toggle_selected_task();
+        task_id
+    };
+    drop(app_state);
+
+    // Reopened without refreshing: everything comes from the store.
+    let app_state = Arc::new(Mutex::new(AppState::with_store(
+        Store::open(&path).unwrap(),
+    )));
+    {
+        let state = app_state.lock().await;
+        assert_eq!(state.pending_changes.len(), 1);
+        assert_eq!(state.tasks.len(), 3);
+        assert!(state
+            .tasks
+            .iter()
+            .any(|t| t.id == task_id && t.is_completed));
+        assert!(state.refreshed_at.is_some());
+    }
+
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.task(&task_id).unwrap().is_completed);
+    let state = app_state.lock().await;
+    assert!(state.pending_changes.is_empty());
+    assert!(state.store.pending_changes().unwrap().is_empty());
+    drop(state);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn the_external_editor_edits_view_tasks_against_the_text_it_opened_with() {
+    let path = local_task_file("external-editor");
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    backend.quick_add_task("Call mum @phone", "").await.unwrap();
+
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    let view = View::Label("phone".to_string());
+    app_state.lock().await.switch_view(view.clone());
+    sync::load_view(&app_state, &backend, &view).await;
+
+    let mut state = app_state.lock().await;
+    let task = state.start_external_edit().expect("the label view's task");
+    assert_eq!(
+        state.input_mode,
+        InputMode::ExternalEditor(Box::new(task.clone()))
+    );
+
+    // Something else changes the description while the editor is open.
+    state.edit_task_text(&task, task.content.clone(), "About Sunday".to_string());
+
+    state.finish_external_edit(Some(("Call mum tonight".to_string(), String::new())));
+    assert_eq!(state.input_mode, InputMode::Normal);
+    let edited = state.view_tasks.iter().find(|t| t.id == task.id).unwrap();
+    assert_eq!(edited.content, "Call mum tonight");
+    assert_eq!(edited.description, "About Sunday");
+    drop(state);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn an_edit_the_server_contradicts_is_held_until_resolved() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+
+    {
+        let mut state = app_state.lock().await;
+        let task = state.tasks.iter().find(|t| t.id == "1").cloned().unwrap();
+        state.edit_task_text(
+            &task,
+            "Buy oat milk".to_string(),
+            "From the corner shop".to_string(),
+        );
+    }
+    server.edit_task(
+        "1",
+        &TaskUpdate {
+            content: Some("Buy soy milk".to_string()),
+            ..Default::default()
+        },
+    );
+    sync::refresh(&app_state, &client).await;
+    {
+        let state = app_state.lock().await;
+        assert_eq!(state.input_mode, InputMode::ResolveConflict);
+        assert_eq!(state.conflicts.len(), 1);
+        let fields = &state.conflicts[0].fields;
+        assert_eq!(fields.len(), 1, "only the content changed on both sides");
+        assert_eq!(fields[0].mine, "Buy oat milk");
+        assert_eq!(fields[0].theirs, "Buy soy milk");
+        assert!(render(&state).contains("theirs: \"Buy soy milk\""));
+    }
+
+    // Nothing is pushed while the conflict is open.
+    sync::flush_all(&app_state, &client).await;
+    assert!(!server.requests().contains(&"POST /tasks/1".to_string()));
+
+    app_state.lock().await.resolve_conflict(Some(false));
+    {
+        let state = app_state.lock().await;
+        assert_eq!(state.input_mode, InputMode::Normal);
+        let task = state.tasks.iter().find(|t| t.id == "1").unwrap();
+        assert_eq!(task.content, "Buy soy milk");
+        assert_eq!(task.description, "From the corner shop");
+    }
+    sync::flush_all(&app_state, &client).await;
+    let task = server.task("1").unwrap();
+    assert_eq!(task.content, "Buy soy milk");
+    assert_eq!(task.description, "From the corner shop");
+}
+
+#[tokio::test]
+async fn edits_to_different_fields_merge_without_a_conflict() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+
+    {
+        let mut state = app_state.lock().await;
+        let task = state.tasks.iter().find(|t| t.id == "2").cloned().unwrap();
+        state.edit_task_text(&task, "Walk the dog twice".to_string(), String::new());
+    }
+    server.edit_task(
+        "2",
+        &TaskUpdate {
+            priority: Some(4),
+            ..Default::default()
+        },
+    );
+    sync::refresh(&app_state, &client).await;
+    {
+        let state = app_state.lock().await;
+        assert!(state.conflicts.is_empty());
+        let task = state.tasks.iter().find(|t| t.id == "2").unwrap();
+        assert_eq!(task.content, "Walk the dog twice");
+        assert_eq!(task.priority, 4);
+    }
+
+    sync::flush_all(&app_state, &client).await;
+    let task = server.task("2").unwrap();
+    assert_eq!(task.content, "Walk the dog twice");
+    assert_eq!(task.priority, 4);
+    assert!(app_state.lock().await.task_bases.is_empty());
+}
+
+#[tokio::test]
+async fn rescheduling_overdue_tasks_keeps_times_and_recurrence() {
+    let yesterday = chrono::Local::now().date_naive() - chrono::Duration::days(1);
+    let weekly = Due {
+        date: yesterday.to_string(),
+        is_recurring: true,
+        datetime: Some(format!("{}T09:30:00", yesterday)),
+        string: "every week at 9:30".to_string(),
+        timezone: None,
+    };
+    let mut tasks = todays_tasks();
+    tasks[0].due = Some(weekly.clone());
+    let (server, client, app_state) = synced(tasks).await;
+
+    let mut state = app_state.lock().await;
+    state.switch_view(View::Label("phone".to_string()));
+    state.confirm_reschedule_overdue(1);
+    assert_eq!(state.input_mode, InputMode::Normal, "only from Today");
+    state.switch_view(View::Today);
+    state.confirm_reschedule_overdue(1);
+    assert_eq!(state.input_mode, InputMode::ConfirmReschedule(1));
+    assert!(render(&state).contains("Move 1 overdue task(s) to tomorrow? (y/n)"));
+
+    state.input_mode = InputMode::Normal;
+    state.reschedule_overdue(1);
+    let tomorrow = yesterday + chrono::Duration::days(2);
+    let due = state.tasks[0].due.clone().unwrap();
+    assert_eq!(due.date, tomorrow.to_string());
+    assert_eq!(due.datetime, Some(format!("{}T09:30:00", tomorrow)));
+    assert_eq!(due.string, "every week at 9:30");
+    assert!(due.is_recurring);
+
+    drop(state);
+
+    // Todoist ends a recurrence given a date alone, so the move goes
+    // through the Sync API along with the due string.
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.requests().contains(&"POST /sync".to_string()));
+    let due = server.task("1").unwrap().due.unwrap();
+    assert_eq!(due.datetime, Some(format!("{}T09:30:00", tomorrow)));
+    assert_eq!(due.string, "every week at 9:30");
+    assert!(due.is_recurring);
+
+    assert!(app_state.lock().await.undo());
+    assert_eq!(app_state.lock().await.tasks[0].due, Some(weekly.clone()));
+    sync::flush_all(&app_state, &client).await;
+    assert_eq!(server.task("1").unwrap().due, Some(weekly));
+}
+
+#[tokio::test]
+async fn undoing_a_synced_delete_restores_the_task_as_it_was() {
+    let mut tasks = todays_tasks();
+    tasks.push(Task {
+        id: "4".to_string(),
+        content: "Return library books".to_string(),
+        labels: vec!["errand".to_string()],
+        section_id: Some("town".to_string()),
+        parent_id: Some("2".to_string()),
+        priority: 1,
+        ..Default::default()
+    });
+    let (server, client, app_state) = synced(tasks).await;
+    let view = View::Label("errand".to_string());
+    app_state.lock().await.switch_view(view.clone());
+    sync::load_view(&app_state, &client, &view).await;
+
+    {
+        let mut state = app_state.lock().await;
+        state.input_mode = InputMode::ConfirmDelete("4".to_string());
+        assert!(render(&state).contains("Delete \"Return library books\"? (y/n)"));
+        state.input_mode = InputMode::Normal;
+        state.toggle_task_by_id("4");
+        state.delete_task("4");
+    }
+    sync::flush_all(&app_state, &client).await;
+    assert!(server.task("4").is_none());
+
+    assert!(app_state.lock().await.undo());
+    sync::flush_all(&app_state, &client).await;
+    let restored = server.task("created-1").expect("restored on the server");
+    assert_eq!(restored.content, "Return library books");
+    assert_eq!(restored.labels, vec!["errand".to_string()]);
+    assert_eq!(restored.section_id.as_deref(), Some("town"));
+    assert_eq!(restored.parent_id.as_deref(), Some("2"));
+    assert!(restored.is_completed);
+
+    let state = app_state.lock().await;
+    assert!(state
+        .tasks
+        .iter()
+        .all(|t| t.content != "Return library books"));
+    assert!(state.completed_tasks.iter().any(|t| t.id == "created-1"));
+    assert!(state.view_tasks.iter().any(|t| t.id == "created-1"));
+    drop(state);
+
+    // The local backend brings it back completed as well.
+    let path = local_task_file("restore");
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    app_state
+        .lock()
+        .await
+        .add_task("Return library books today");
+    sync::flush_all(&app_state, &backend).await;
+    {
+        let mut state = app_state.lock().await;
+        let task_id = state.tasks[0].id.clone();
+        state.toggle_task_by_id(&task_id);
+        state.delete_task(&task_id);
+    }
+    sync::flush_all(&app_state, &backend).await;
+    assert!(app_state.lock().await.undo());
+    sync::flush_all(&app_state, &backend).await;
+
+    let app_state = Arc::new(Mutex::new(AppState::new()));
+    sync::refresh(&app_state, &backend).await;
+    let state = app_state.lock().await;
+    assert!(state.tasks.is_empty());
+    assert!(state
+        .completed_tasks
+        .iter()
+        .any(|t| t.content == "Return library books" && t.is_completed));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn undoing_the_delete_of_a_parent_restores_its_subtasks_under_it() {
+    for incremental in [false, true] {
+        let mut tasks = todays_tasks();
+        tasks.push(Task {
+            parent_id: Some("2".to_string()),
+            ..task_due_today("4", "Buy a leash")
+        });
+        tasks.push(Task {
+            parent_id: Some("4".to_string()),
+            ..task_due_today("5", "Measure the dog")
+        });
+        let (server, client, app_state) = match incremental {
+            true => synced_incrementally(tasks).await,
+            false => synced(tasks).await,
+        };
+
+        {
+            let mut state = app_state.lock().await;
+            state.input_mode = InputMode::ConfirmDelete("2".to_string());
+            assert!(render(&state).contains("Delete \"Walk the dog\" and its 2 subtask(s)? (y/n)"));
+            state.input_mode = InputMode::Normal;
+            state.delete_task("2");
+            let ids: Vec<&str> = state.tasks.iter().map(|t| t.id.as_str()).collect();
+            assert_eq!(ids, vec!["1", "3"], "the subtree goes at once");
+        }
+        sync::flush_all(&app_state, &client).await;
+        assert!(["2", "4", "5"].iter().all(|id| server.task(id).is_none()));
+
+        assert!(app_state.lock().await.undo());
+        sync::flush_all(&app_state, &client).await;
+        let parent = server.task("created-1").expect("parent restored");
+        assert_eq!(parent.content, "Walk the dog");
+        assert_eq!(parent.parent_id, None);
+        let subtask = server.task("created-2").expect("subtask restored");
+        assert_eq!(subtask.content, "Buy a leash");
+        assert_eq!(subtask.parent_id.as_deref(), Some("created-1"));
+        let nested = server.task("created-3").expect("nested subtask restored");
+        assert_eq!(nested.parent_id.as_deref(), Some("created-2"));
+
+        let state = app_state.lock().await;
+        let tree: Vec<(&str, Option<&str>)> = state
+            .tasks
+            .iter()
+            .map(|t| (t.id.as_str(), t.parent_id.as_deref()))
+            .collect();
+        assert!(
+            tree.contains(&("created-2", Some("created-1"))),
+            "{:?}",
+            tree
+        );
+        assert!(
+            tree.contains(&("created-3", Some("created-2"))),
+            "{:?}",
+            tree
+        );
+        assert!(state.pending_changes.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn refresh_keeps_the_selected_task_selected() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+    let tomorrow = (chrono::Local::now().date_naive() + chrono::Duration::days(1)).to_string();
+    let move_to_tomorrow = |task_id: &str| {
+        server.edit_task(
+            task_id,
+            &TaskUpdate {
+                due_date: Some(tomorrow.clone()),
+                ..Default::default()
+            },
+        )
+    };
+
+    app_state.lock().await.go_to_bottom();
+    assert_eq!(app_state.lock().await.selected_task_id().unwrap(), "3");
+    move_to_tomorrow("1");
+    sync::refresh(&app_state, &client).await;
+    assert_eq!(app_state.lock().await.selected_task_id().unwrap(), "3");
+
+    // Once the selected task is gone, the one after it takes its place.
+    app_state.lock().await.go_to_top();
+    assert_eq!(app_state.lock().await.selected_task_id().unwrap(), "2");
+    move_to_tomorrow("2");
+    sync::refresh(&app_state, &client).await;
+    assert_eq!(app_state.lock().await.selected_task_id().unwrap(), "3");
+}
+
+/// Wait up to a few seconds for `done` to hold of the state.
+async fn wait_for(app_state: &Arc<Mutex<AppState>>, done: impl Fn(&AppState) -> bool) -> bool {
+    for _ in 0..100 {
+        if done(&*app_state.lock().await) {
+            return true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn background_sync_picks_up_changes_but_waits_for_open_prompts_and_editors() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+    let client = Arc::new(client);
+    server.edit_task(
+        "1",
+        &TaskUpdate {
+            content: Some("Buy oat milk".to_string()),
+            ..Default::default()
+        },
+    );
+    app_state.lock().await.input_mode = InputMode::AddTask;
+
+    let auto_sync = sync::spawn_auto_sync_loop(
+        app_state.clone(),
+        client.clone(),
+        std::time::Duration::from_millis(100),
+    );
+    let renamed = |state: &AppState| state.tasks.iter().any(|t| t.content == "Buy oat milk");
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert!(!renamed(&*app_state.lock().await), "refreshed mid-prompt");
+
+    // Nor while the selected task is open in $EDITOR.
+    {
+        let mut state = app_state.lock().await;
+        state.input_mode = InputMode::Normal;
+        assert!(state.start_external_edit().is_some());
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert!(!renamed(&*app_state.lock().await), "refreshed mid-edit");
+
+    app_state.lock().await.finish_external_edit(None);
+    assert!(wait_for(&app_state, renamed).await, "never refreshed");
+    auto_sync.abort();
+    let state = app_state.lock().await;
+    assert_eq!(state.sync_status, SyncStatus::Online);
+    assert!(render(&state).contains("Online (synced just now)"));
+}
+
+#[tokio::test]
+async fn background_sync_notices_the_connection_is_back_without_waiting_out_the_backoff() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+    server.edit_task(
+        "1",
+        &TaskUpdate {
+            content: Some("Buy oat milk".to_string()),
+            ..Default::default()
+        },
+    );
+    // As if the last refresh found no connection.
+    app_state.lock().await.sync_status = SyncStatus::Offline;
+
+    let auto_sync = sync::spawn_auto_sync_loop(
+        app_state.clone(),
+        Arc::new(client),
+        std::time::Duration::from_secs(60),
+    );
+    let renamed = |state: &AppState| state.tasks.iter().any(|t| t.content == "Buy oat milk");
+    assert!(
+        wait_for(&app_state, renamed).await,
+        "waited for the next refresh"
+    );
+    auto_sync.abort();
+    assert_eq!(app_state.lock().await.sync_status, SyncStatus::Online);
+    assert_eq!(count_requests(&server, "GET /labels"), 3, "probed once");
+}
+
+#[tokio::test]
+async fn the_local_backend_applies_a_replayed_request_once() {
+    let path = local_task_file("replay");
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    let created = backend.quick_add_task("Pay rent", "key-1").await.unwrap();
+    backend.delete_task(&created.id, "key-2").await.unwrap();
+    drop(backend);
+
+    // As if the app stopped before recording either change as done.
+    let backend = LocalBackend::open(path.clone()).unwrap();
+    let replayed = backend.quick_add_task("Pay rent", "key-1").await;
+    assert!(matches!(replayed, Err(crate::api::ApiError::NotFound)));
+    backend.delete_task(&created.id, "key-2").await.unwrap();
+    assert!(backend
+        .get_tasks_by_filter("no date")
+        .await
+        .unwrap()
+        .is_empty());
+
+    let again = backend.quick_add_task("Pay rent", "key-3").await.unwrap();
+    let retried = backend.quick_add_task("Pay rent", "key-3").await.unwrap();
+    assert_eq!(again.id, retried.id);
+    assert_eq!(
+        backend.get_tasks_by_filter("no date").await.unwrap().len(),
+        1
+    );
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn a_forbidden_change_is_rolled_back_without_asking_for_a_token() {
+    let (server, client, app_state) = synced(todays_tasks()).await;
+
+    app_state.lock().await.toggle_selected_task();
+    server.fail_next(StatusCode::FORBIDDEN, None);
+    sync::flush_all(&app_state, &client).await;
+
+    let state = app_state.lock().await;
+    assert!(state.pending_changes.is_empty());
+    assert!(state.tasks.iter().all(|t| !t.is_completed));
+    assert_eq!(state.input_mode, InputMode::Normal);
+    assert!(matches!(state.sync_status, SyncStatus::Error(_)));
+}
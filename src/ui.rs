@@ -6,22 +6,29 @@
 //! - Beautiful terminal interface
 //! - Responsive user interactions
 
-use crate::state::AppState;
+use crate::api::Task;
+use crate::backend::Backend;
+use crate::conflict::Conflict;
+use crate::state::{AppState, EditForm, InputMode, ProjectRow, View, EDIT_FORM_FIELDS};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
-use std::io;
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::time::Duration;
+use uuid::Uuid;
 
 /// Returns a spinner frame using OSC 8. Uses a simple 4-frame spinner.
 fn spinner_frame() -> &'static str {
@@ -36,6 +43,82 @@ fn spinner_frame() -> &'static str {
     frames[index]
 }
 
+/// Formats how long ago something happened, e.g. "just now" or "5m ago".
+fn format_age(age: chrono::Duration) -> String {
+    if age.num_days() > 0 {
+        format!("{}d ago", age.num_days())
+    } else if age.num_hours() > 0 {
+        format!("{}h ago", age.num_hours())
+    } else if age.num_minutes() > 0 {
+        format!("{}m ago", age.num_minutes())
+    } else {
+        "just now".to_string()
+    }
+}
+
+/// Maps a Todoist color name (as used by projects and labels) to its RGB value.
+fn todoist_color(name: &str) -> Color {
+    match name {
+        "berry_red" => Color::Rgb(0xb8, 0x25, 0x5f),
+        "red" => Color::Rgb(0xdb, 0x40, 0x35),
+        "orange" => Color::Rgb(0xff, 0x99, 0x33),
+        "yellow" => Color::Rgb(0xfa, 0xd0, 0x00),
+        "olive_green" => Color::Rgb(0xaf, 0xb8, 0x3b),
+        "lime_green" => Color::Rgb(0x7e, 0xcc, 0x49),
+        "green" => Color::Rgb(0x29, 0x94, 0x38),
+        "mint_green" => Color::Rgb(0x6a, 0xcc, 0xbc),
+        "teal" => Color::Rgb(0x15, 0x8f, 0xad),
+        "sky_blue" => Color::Rgb(0x14, 0xaa, 0xf5),
+        "light_blue" => Color::Rgb(0x96, 0xc3, 0xeb),
+        "blue" => Color::Rgb(0x40, 0x73, 0xff),
+        "grape" => Color::Rgb(0x88, 0x4d, 0xff),
+        "violet" => Color::Rgb(0xaf, 0x38, 0xeb),
+        "lavender" => Color::Rgb(0xeb, 0x96, 0xeb),
+        "magenta" => Color::Rgb(0xe0, 0x51, 0x94),
+        "salmon" => Color::Rgb(0xff, 0x8d, 0x85),
+        "charcoal" => Color::Rgb(0x80, 0x80, 0x80),
+        "grey" => Color::Rgb(0xb8, 0xb8, 0xb8),
+        "taupe" => Color::Rgb(0xcc, 0xac, 0x93),
+        _ => Color::Gray,
+    }
+}
+
+/// Returns a rect `percent_x`% as wide as `area` and `height` rows tall,
+/// centered within it.
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(height) / 2,
+        width,
+        height: height.min(area.height),
+    }
+}
+
+/// Runs `$EDITOR` (or `vi`) on `path`, waiting for it to exit. `$EDITOR` may
+/// include arguments, e.g. `code --wait`.
+fn run_editor(path: &std::path::Path) -> io::Result<std::process::ExitStatus> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    std::process::Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+}
+
+/// Splits the editor file back into content (the `# ` heading) and description
+/// (everything after it).
+fn parse_editor_markdown(text: &str) -> (String, String) {
+    let mut lines = text.lines().skip_while(|line| line.trim().is_empty());
+    let content = lines
+        .next()
+        .map(|line| line.trim_start_matches('#').trim().to_string())
+        .unwrap_or_default();
+    let description = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+    (content, description)
+}
+
 /// Minimal markdown parser: strips common markdown symbols and converts link syntax.
 fn parse_markdown(text: &str) -> String {
     // Remove bold & italic markers and underscores.
@@ -49,9 +132,43 @@ fn parse_markdown(text: &str) -> String {
     cleaned
 }
 
+/// What's left to do after a prompt is submitted.
+enum PromptOutcome {
+    /// Fetch the tasks of the view the prompt switched to.
+    LoadView(View),
+    /// Retry with the API token the user typed in.
+    NewToken(String),
+}
+
 pub struct UI {
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
     list_state: ListState,
+    /// First key of a two-key command such as `dd`.
+    pending_key: Option<char>,
+    scroll: ScrollOffsets,
+}
+
+/// How far each task list is scrolled, by title. Kept between frames so a
+/// list stays where it was when redrawn or reloaded, rather than jumping
+/// back to its first task.
+#[derive(Default)]
+pub(crate) struct ScrollOffsets(HashMap<String, usize>);
+
+impl ScrollOffsets {
+    /// Where the list called `title` was scrolled to, kept within its
+    /// `len` items shown in `area`.
+    fn offset(&self, title: &str, len: usize, area: Rect) -> usize {
+        let visible = area.height.saturating_sub(2) as usize;
+        self.0
+            .get(title)
+            .copied()
+            .unwrap_or(0)
+            .min(len.saturating_sub(visible))
+    }
+
+    fn set(&mut self, title: &str, offset: usize) {
+        self.0.insert(title.to_string(), offset);
+    }
 }
 
 impl UI {
@@ -69,38 +186,94 @@ impl UI {
         Ok(Self {
             terminal,
             list_state,
+            pending_key: None,
+            scroll: ScrollOffsets::default(),
         })
     }
 
     /// Main UI loop - handles events and rendering
-    pub async fn run(
+    pub async fn run<B: Backend>(
         &mut self,
         app_state: std::sync::Arc<tokio::sync::Mutex<crate::state::AppState>>,
-        client: std::sync::Arc<crate::api::TodoistClient>,
+        backend: std::sync::Arc<B>,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        // The last state rendered, drawn again while the lock is contended
+        let mut snapshot = app_state.lock().await.clone();
         loop {
             {
                 // Lock state for rendering
-                let state = app_state.lock().await;
+                let mut state = app_state.lock().await;
                 self.list_state.select(Some(state.selected_index));
+                state.retrying_at = backend.retrying_at();
             }
 
             // Render using a nonblocking try_lock snapshot, so that UI always refreshes
-            self.terminal.draw(|f| {
-                let state_copy = if let Ok(guard) = app_state.try_lock() {
-                    guard.clone()
-                } else {
-                    AppState::new()
-                };
-                Self::render_ui(f, &state_copy);
-            })?;
+            if let Ok(guard) = app_state.try_lock() {
+                snapshot = guard.clone();
+            }
+            self.terminal
+                .draw(|f| Self::render_ui(f, &snapshot, &mut self.scroll))?;
 
             // Handle input with timeout polling
             if event::poll(Duration::from_millis(200))? {
                 if let Event::Key(key) = event::read()? {
                     if key.kind == KeyEventKind::Press {
+                        {
+                            // While a prompt is open, every key goes to it.
+                            let mut state = app_state.lock().await;
+                            if state.input_mode != InputMode::Normal {
+                                let outcome = Self::handle_input_key(&mut state, key.code);
+                                if let Some(PromptOutcome::NewToken(_)) = &outcome {
+                                    state.sync_status = crate::state::SyncStatus::Syncing;
+                                }
+                                drop(state);
+                                let app_state_clone = app_state.clone();
+                                let backend_clone = backend.clone();
+                                match outcome {
+                                    Some(PromptOutcome::LoadView(view)) => {
+                                        tokio::spawn(async move {
+                                            crate::sync::load_view(
+                                                &app_state_clone,
+                                                backend_clone.as_ref(),
+                                                &view,
+                                            )
+                                            .await;
+                                        });
+                                    }
+                                    Some(PromptOutcome::NewToken(token)) => {
+                                        backend.set_api_token(token);
+                                        tokio::spawn(async move {
+                                            crate::sync::refresh(
+                                                &app_state_clone,
+                                                backend_clone.as_ref(),
+                                            )
+                                            .await;
+                                        });
+                                    }
+                                    None => {}
+                                }
+                                continue;
+                            }
+                        }
+                        let pending_key = self.pending_key.take();
                         match key.code {
                             KeyCode::Char('q') => break,
+                            KeyCode::Char('d') if pending_key == Some('d') => {
+                                let mut state = app_state.lock().await;
+                                if let Some(task_id) = state.selected_task_id() {
+                                    state.input_mode = InputMode::ConfirmDelete(task_id);
+                                }
+                            }
+                            KeyCode::Char('d') => self.pending_key = Some('d'),
+                            KeyCode::Char('a') if pending_key == Some('z') => {
+                                let mut state = app_state.lock().await;
+                                if !state.toggle_selected_subtasks()
+                                    && matches!(state.view, View::Project(_))
+                                {
+                                    state.toggle_selected_section();
+                                }
+                            }
+                            KeyCode::Char('z') => self.pending_key = Some('z'),
                             KeyCode::Char('j') | KeyCode::Down => {
                                 let mut state = app_state.lock().await;
                                 state.move_down();
@@ -118,23 +291,84 @@ impl UI {
                                 state.go_to_top();
                             }
                             KeyCode::Char(' ') => {
-                                // First, obtain a snapshot of the unified today view and extract the selected task ID.
-                                let selected_id_opt = {
-                                    let state = app_state.lock().await;
-                                    let unified_ids: Vec<String> = state
-                                        .today_tasks()
-                                        .into_iter()
-                                        .map(|t| t.id.clone())
-                                        .collect();
-                                    unified_ids.get(state.selected_index).cloned()
-                                };
-
-                                // If a task id was found, lock mutably and toggle that task.
-                                if let Some(selected_id) = selected_id_opt {
+                                // Toggle the selected task locally and queue the change; the
+                                // background flush loop pushes it once the grace period ends.
+                                let mut state = app_state.lock().await;
+                                state.toggle_selected_task();
+                            }
+                            KeyCode::Char('t') | KeyCode::Char('T') => {
+                                // Bulk-reschedule everything overdue to today (t) or tomorrow (T)
+                                let mut state = app_state.lock().await;
+                                let days = if key.code == KeyCode::Char('T') { 1 } else { 0 };
+                                state.confirm_reschedule_overdue(days);
+                            }
+                            KeyCode::Char('a') | KeyCode::Char('o') => {
+                                let mut state = app_state.lock().await;
+                                state.input_mode = InputMode::AddTask;
+                                state.input_buffer.clear();
+                            }
+                            KeyCode::Char('c') => {
+                                let mut state = app_state.lock().await;
+                                state.start_resolving_conflicts();
+                            }
+                            KeyCode::Char(':') => {
+                                let mut state = app_state.lock().await;
+                                state.input_mode = InputMode::Command;
+                                state.input_buffer.clear();
+                            }
+                            KeyCode::Char('e') => {
+                                let mut state = app_state.lock().await;
+                                state.start_editing_selected();
+                            }
+                            KeyCode::Char('E') => {
+                                let task = app_state.lock().await.start_external_edit();
+                                if let Some(task) = task {
+                                    let edited = self.edit_in_external_editor(&task).await;
                                     let mut state = app_state.lock().await;
-                                    state.toggle_task_by_id(&selected_id);
+                                    match edited {
+                                        Ok(edited) => state.finish_external_edit(edited),
+                                        Err(e) => {
+                                            state.finish_external_edit(None);
+                                            state.sync_status = crate::state::SyncStatus::Error(
+                                                format!("Editor: {}", e),
+                                            );
+                                        }
+                                    }
                                 }
                             }
+                            KeyCode::Char('h') | KeyCode::Left => {
+                                let mut state = app_state.lock().await;
+                                state.toggle_sidebar();
+                            }
+                            KeyCode::Char('l') | KeyCode::Right | KeyCode::Enter => {
+                                let view = {
+                                    let mut state = app_state.lock().await;
+                                    if !state.sidebar.focused {
+                                        continue;
+                                    }
+                                    state.open_sidebar_selection()
+                                };
+                                if let Some(view) = view {
+                                    let app_state_clone = app_state.clone();
+                                    let backend_clone = backend.clone();
+                                    tokio::spawn(async move {
+                                        crate::sync::load_view(
+                                            &app_state_clone,
+                                            backend_clone.as_ref(),
+                                            &view,
+                                        )
+                                        .await;
+                                    });
+                                }
+                            }
+                            KeyCode::Char('u') => {
+                                let mut state = app_state.lock().await;
+                                state.undo();
+                            }
+                            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                let mut state = app_state.lock().await;
+                                state.redo();
+                            }
                             KeyCode::Char('r') => {
                                 {
                                     // Immediately mark state as syncing
@@ -143,50 +377,10 @@ impl UI {
                                 }
                                 // Spawn a background task for refresh so UI rendering is not blocked
                                 let app_state_clone = app_state.clone();
-                                let client_clone = client.clone();
+                                let backend_clone = backend.clone();
                                 tokio::spawn(async move {
-                                    use tokio::time::{timeout, Duration};
-                                    // Refresh active tasks with timeout
-                                    let active_result = timeout(
-                                        Duration::from_secs(5),
-                                        client_clone.get_todays_tasks(),
-                                    )
-                                    .await;
-                                    // Refresh completed tasks with timeout
-                                    let completed_result = timeout(
-                                        Duration::from_secs(5),
-                                        client_clone.get_todays_completed_tasks(),
-                                    )
-                                    .await;
-                                    let mut state = app_state_clone.lock().await;
-                                    match active_result {
-                                        Ok(Ok(tasks)) => {
-                                            state.load_tasks(tasks);
-                                            state.sync_status = crate::state::SyncStatus::Online;
-                                        }
-                                        Ok(Err(e)) => {
-                                            eprintln!("Error refreshing tasks: {}", e);
-                                            state.sync_status =
-                                                crate::state::SyncStatus::Error(e.to_string());
-                                        }
-                                        Err(_) => {
-                                            eprintln!("Refresh tasks timed out");
-                                            state.sync_status = crate::state::SyncStatus::Error(
-                                                "Timeout".to_string(),
-                                            );
-                                        }
-                                    }
-                                    match completed_result {
-                                        Ok(Ok(completed)) => {
-                                            state.load_completed_tasks(completed);
-                                        }
-                                        Ok(Err(e)) => {
-                                            eprintln!("Error refreshing completed tasks: {}", e);
-                                        }
-                                        Err(_) => {
-                                            eprintln!("Refresh completed tasks timed out");
-                                        }
-                                    }
+                                    crate::sync::refresh(&app_state_clone, backend_clone.as_ref())
+                                        .await;
                                 });
                             }
                             _ => {}
@@ -201,41 +395,489 @@ impl UI {
         Ok(())
     }
 
-    fn render_ui(f: &mut Frame, app_state: &AppState) {
+    /// Handle a key press while a text prompt is open, returning what the
+    /// caller still has to do once the prompt is submitted.
+    fn handle_input_key(state: &mut AppState, code: KeyCode) -> Option<PromptOutcome> {
+        if state.input_mode == InputMode::EditTask {
+            Self::handle_edit_form_key(state, code);
+            return None;
+        }
+        if state.input_mode == InputMode::ResolveConflict {
+            Self::handle_conflict_key(state, code);
+            return None;
+        }
+        if let InputMode::ConfirmDelete(task_id) = &state.input_mode {
+            let task_id = task_id.clone();
+            state.input_mode = InputMode::Normal;
+            if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                state.delete_task(&task_id);
+            }
+            return None;
+        }
+        if let InputMode::ConfirmReschedule(days) = state.input_mode {
+            state.input_mode = InputMode::Normal;
+            if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                state.reschedule_overdue(days);
+            }
+            return None;
+        }
+        if let InputMode::ConfirmComplete(task_id) = &state.input_mode {
+            let task_id = task_id.clone();
+            state.input_mode = InputMode::Normal;
+            if matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                state.toggle_task_by_id(&task_id);
+            }
+            return None;
+        }
+        match code {
+            KeyCode::Esc => {
+                state.input_mode = InputMode::Normal;
+                state.input_buffer.clear();
+            }
+            KeyCode::Enter => {
+                let text = std::mem::take(&mut state.input_buffer);
+                let mode = std::mem::replace(&mut state.input_mode, InputMode::Normal);
+                match mode {
+                    InputMode::AddTask => state.add_task(&text),
+                    InputMode::Command => {
+                        return state.run_command(&text).map(PromptOutcome::LoadView)
+                    }
+                    InputMode::EnterToken if !text.trim().is_empty() => {
+                        return Some(PromptOutcome::NewToken(text.trim().to_string()))
+                    }
+                    _ => {}
+                }
+            }
+            KeyCode::Backspace => {
+                state.input_buffer.pop();
+            }
+            KeyCode::Char(c) => state.input_buffer.push(c),
+            _ => {}
+        }
+        None
+    }
+
+    /// Handle a key press while the edit form is open.
+    fn handle_edit_form_key(state: &mut AppState, code: KeyCode) {
+        match code {
+            KeyCode::Esc => state.finish_editing(false),
+            KeyCode::Enter => state.finish_editing(true),
+            _ => {
+                let Some(form) = state.edit_form.as_mut() else {
+                    return;
+                };
+                match code {
+                    KeyCode::Tab | KeyCode::Down => form.focus_next(),
+                    KeyCode::BackTab | KeyCode::Up => form.focus_previous(),
+                    KeyCode::Backspace => {
+                        form.focused_field_mut().pop();
+                    }
+                    KeyCode::Char(c) => form.focused_field_mut().push(c),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Handle a key press while the conflict resolution dialog is open.
+    fn handle_conflict_key(state: &mut AppState, code: KeyCode) {
+        match code {
+            KeyCode::Esc => state.input_mode = InputMode::Normal,
+            KeyCode::Char('m') => state.resolve_conflict(Some(true)),
+            KeyCode::Char('t') => state.resolve_conflict(Some(false)),
+            KeyCode::Enter => state.resolve_conflict(None),
+            _ => {
+                let Some(conflict) = state.conflicts.first_mut() else {
+                    state.input_mode = InputMode::Normal;
+                    return;
+                };
+                match code {
+                    KeyCode::Char('j') | KeyCode::Down | KeyCode::Tab => conflict.focus_next(),
+                    KeyCode::Char('k') | KeyCode::Up | KeyCode::BackTab => {
+                        conflict.focus_previous()
+                    }
+                    KeyCode::Char(' ') | KeyCode::Left | KeyCode::Right => {
+                        conflict.toggle_focused()
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub(crate) fn render_ui(f: &mut Frame, app_state: &AppState, scroll: &mut ScrollOffsets) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Min(0), Constraint::Length(3)])
             .split(f.size());
 
-        // Render two sections for tasks
-        let task_area = chunks[0];
+        let mut main_area = chunks[0];
+        if app_state.sidebar.open {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(28), Constraint::Min(0)])
+                .split(chunks[0]);
+            Self::render_sidebar(f, columns[0], app_state);
+            main_area = columns[1];
+        }
+
+        match &app_state.view {
+            View::Today => Self::render_today_view(f, main_area, app_state, scroll),
+            View::Project(_) => Self::render_project_view(f, main_area, app_state, scroll),
+            View::Label(name) => Self::render_tasks_section(
+                &format!("@{}", name),
+                &app_state.flat_view_tasks(),
+                f,
+                main_area,
+                0,
+                app_state,
+                scroll,
+            ),
+            View::Filter(query) => {
+                let title = match app_state.current_saved_view() {
+                    Some(saved) => format!("{} ({})", saved.name, query),
+                    None => format!("Filter: {} (:save <name> to keep)", query),
+                };
+                Self::render_tasks_section(
+                    &title,
+                    &app_state.flat_view_tasks(),
+                    f,
+                    main_area,
+                    0,
+                    app_state,
+                    scroll,
+                )
+            }
+        }
+
+        // Render status bar
+        Self::render_status_bar(f, chunks[1], app_state);
+
+        if let Some(form) = &app_state.edit_form {
+            Self::render_edit_form(f, form);
+        }
+        if app_state.input_mode == InputMode::ResolveConflict {
+            if let Some(conflict) = app_state.conflicts.first() {
+                Self::render_conflict_dialog(f, conflict);
+            }
+        }
+    }
+
+    /// Render the Overdue, Today and Upcoming sections.
+    fn render_today_view(
+        f: &mut Frame,
+        area: Rect,
+        app_state: &AppState,
+        scroll: &mut ScrollOffsets,
+    ) {
+        // Render task sections; Overdue only appears when something is overdue
+        let overdue = app_state.tasks_overdue();
+        let overdue_height = if overdue.is_empty() {
+            0
+        } else {
+            (overdue.len() as u16 + 2).min(8)
+        };
         let vertical_chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Length(10), Constraint::Min(0)])
-            .split(task_area);
+            .constraints([
+                Constraint::Length(overdue_height),
+                Constraint::Length(10),
+                Constraint::Min(0),
+            ])
+            .split(area);
+
+        if !overdue.is_empty() {
+            Self::render_tasks_section(
+                "Overdue (t: move to today, T: to tomorrow)",
+                &overdue,
+                f,
+                vertical_chunks[0],
+                0,
+                app_state,
+                scroll,
+            );
+        }
 
-        // Render merged "Today" tasks (active + completed)
+        // Render merged "Today" tasks (active + completed); offset equals the overdue count
         Self::render_tasks_section(
             "Today",
             &app_state.today_tasks(),
             f,
-            vertical_chunks[0],
-            0,
-            app_state.selected_index,
+            vertical_chunks[1],
+            overdue.len(),
+            app_state,
+            scroll,
         );
 
-        // Render Upcoming tasks; offset equals the count of today_tasks
+        // Render Upcoming tasks; offset equals the count of selectable tasks above
         Self::render_tasks_section(
             "Upcoming",
             &app_state.tasks_upcoming(),
             f,
-            vertical_chunks[1],
-            app_state.today_tasks().len(),
-            app_state.selected_index,
+            vertical_chunks[2],
+            app_state.unified_today_count(),
+            app_state,
+            scroll,
         );
+    }
 
-        // Render status bar
-        Self::render_status_bar(f, chunks[1], app_state);
+    /// Render the tasks of the project open in the sidebar.
+    fn render_project_view(
+        f: &mut Frame,
+        area: Rect,
+        app_state: &AppState,
+        scroll: &mut ScrollOffsets,
+    ) {
+        let title = app_state
+            .current_project()
+            .map(|p| p.name.as_str())
+            .unwrap_or("Project");
+        let rows = app_state.project_rows();
+        let shown: Vec<&Task> = rows
+            .iter()
+            .filter_map(|row| match row {
+                ProjectRow::Task(task) => Some(*task),
+                ProjectRow::Section { .. } => None,
+            })
+            .collect();
+        let items: Vec<ListItem> = rows
+            .into_iter()
+            .map(|row| match row {
+                ProjectRow::Section {
+                    section,
+                    task_count,
+                    folded,
+                } => {
+                    let marker = if folded { "▸" } else { "▾" };
+                    ListItem::new(Line::from(Span::styled(
+                        format!("{} {} ({})", marker, section.name, task_count),
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    )))
+                }
+                ProjectRow::Task(task) => Self::task_list_item(task, &shown, app_state),
+            })
+            .collect();
+
+        let row_count = items.len();
+        let mut project_state = ListState::default()
+            .with_selected(Some(app_state.selected_index))
+            .with_offset(scroll.offset(title, row_count, area));
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("{} (za: fold section)", title)),
+            )
+            .highlight_style(
+                Style::default()
+                    .bg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .highlight_symbol("> ");
+        f.render_stateful_widget(list, area, &mut project_state);
+        scroll.set(title, project_state.offset());
+    }
+
+    /// Render the project list on the left.
+    fn render_sidebar(f: &mut Frame, area: Rect, app_state: &AppState) {
+        let entry_style = |view: View| {
+            if app_state.view == view {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            }
+        };
+        let mut items = vec![ListItem::new(Span::styled(
+            "Today",
+            entry_style(View::Today),
+        ))];
+        items.extend(app_state.saved_views.iter().map(|saved| {
+            ListItem::new(Span::styled(
+                format!("≡ {}", saved.name),
+                entry_style(View::Filter(saved.query.clone())),
+            ))
+        }));
+        items.extend(app_state.projects.iter().map(|project| {
+            let indent = "  ".repeat(app_state.project_depth(project));
+            ListItem::new(Span::styled(
+                format!("{}# {}", indent, project.name),
+                entry_style(View::Project(project.id.clone())),
+            ))
+        }));
+        items.extend(app_state.labels.iter().map(|label| {
+            ListItem::new(Span::styled(
+                format!("@{}", label.name),
+                entry_style(View::Label(label.name.clone())).fg(todoist_color(&label.color)),
+            ))
+        }));
+
+        let mut sidebar_state = ListState::default();
+        sidebar_state.select(Some(app_state.sidebar.selected));
+        let highlight = if app_state.sidebar.focused {
+            Style::default()
+                .bg(Color::Blue)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::Blue)
+        };
+
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Views"))
+            .highlight_style(highlight)
+            .highlight_symbol("> ");
+        f.render_stateful_widget(list, area, &mut sidebar_state);
+    }
+
+    /// Render the edit form as a popup over the task list.
+    fn render_edit_form(f: &mut Frame, form: &EditForm) {
+        let area = centered_rect(70, EDIT_FORM_FIELDS.len() as u16 + 2, f.size());
+        let lines: Vec<Line> = EDIT_FORM_FIELDS
+            .iter()
+            .zip(form.fields.iter())
+            .enumerate()
+            .map(|(i, (label, value))| {
+                let label_style = if i == form.focused {
+                    Style::default()
+                        .fg(Color::Blue)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                Line::from(vec![
+                    Span::styled(format!("{:>12}: ", label), label_style),
+                    Span::raw(value.clone()),
+                ])
+            })
+            .collect();
+
+        let paragraph = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Edit task (Tab: next field, Enter: save, Esc: cancel)"),
+        );
+        f.render_widget(Clear, area);
+        f.render_widget(paragraph, area);
+
+        let cursor_x = area.x + 1 + 14 + form.fields[form.focused].chars().count() as u16;
+        f.set_cursor(
+            cursor_x.min(area.right().saturating_sub(2)),
+            area.y + 1 + form.focused as u16,
+        );
+    }
+
+    /// Render the conflict resolution dialog as a popup over the task list:
+    /// one row per field both sides changed, with the value that will be kept
+    /// highlighted.
+    fn render_conflict_dialog(f: &mut Frame, conflict: &Conflict) {
+        let area = centered_rect(80, conflict.fields.len() as u16 * 3 + 3, f.size());
+        let chosen = Style::default()
+            .fg(Color::Green)
+            .add_modifier(Modifier::BOLD);
+        let dropped = Style::default().fg(Color::DarkGray);
+        let mut lines = vec![Line::from(vec![
+            Span::raw("Changed here and on Todoist: "),
+            Span::styled(
+                parse_markdown(&conflict.theirs.content),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+        ])];
+        for (i, field) in conflict.fields.iter().enumerate() {
+            let label_style = if i == conflict.focused {
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            let (mine_style, theirs_style) = if field.keep_mine {
+                (chosen, dropped)
+            } else {
+                (dropped, chosen)
+            };
+            lines.push(Line::from(vec![
+                Span::styled(format!("{:>12}: ", field.field.label()), label_style),
+                Span::styled(format!("was {:?}", field.base), dropped),
+            ]));
+            lines.push(Line::from(vec![
+                Span::raw(" ".repeat(14)),
+                Span::styled(format!("mine:   {:?}", field.mine), mine_style),
+            ]));
+            lines.push(Line::from(vec![
+                Span::raw(" ".repeat(14)),
+                Span::styled(format!("theirs: {:?}", field.theirs), theirs_style),
+            ]));
+        }
+
+        let paragraph = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(
+            "Conflict (m: keep mine, t: keep theirs, space: pick per field, Enter: apply, Esc: later)",
+        ));
+        f.render_widget(Clear, area);
+        f.render_widget(paragraph, area);
+    }
+
+    /// A task's line: indentation and fold marker for subtasks, checkbox,
+    /// content, label chips and a truncated description. `shown` is the list the task is
+    /// rendered in, which decides how deep it is indented.
+    fn task_list_item(task: &Task, shown: &[&Task], app_state: &AppState) -> ListItem<'static> {
+        let status_symbol = if task.is_completed { "✓" } else { " " };
+        let indent = "  ".repeat(app_state.subtask_depth(task, shown));
+        let fold_marker = if !app_state.has_subtasks(&task.id) {
+            ""
+        } else if app_state.folded_tasks.contains(&task.id) {
+            "▸ "
+        } else {
+            "▾ "
+        };
+
+        // Process markdown from both content and description.
+        let content_md = parse_markdown(&task.content);
+        let desc_md = parse_markdown(&task.description);
+        let desc_truncated = if !desc_md.is_empty() {
+            if desc_md.len() > 100 {
+                format!(" - {}...", &desc_md[..100])
+            } else {
+                format!(" - {}", desc_md)
+            }
+        } else {
+            String::new()
+        };
+
+        let style = if task.is_completed {
+            Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::CROSSED_OUT)
+        } else {
+            Style::default()
+        };
+        let mut spans = vec![Span::styled(
+            format!(
+                "{}{}[{}] {}",
+                indent, fold_marker, status_symbol, content_md
+            ),
+            style,
+        )];
+        // Label chips go between the content and the description.
+        for name in &task.labels {
+            let color = if task.is_completed {
+                Color::DarkGray
+            } else {
+                app_state
+                    .labels
+                    .iter()
+                    .find(|l| l.name == *name)
+                    .map_or(Color::Gray, |l| todoist_color(&l.color))
+            };
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(
+                format!("@{}", name),
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::styled(desc_truncated, style));
+        ListItem::new(Line::from(spans))
     }
 
     fn render_tasks_section(
@@ -244,39 +886,16 @@ impl UI {
         f: &mut Frame,
         area: ratatui::layout::Rect,
         offset: usize,
-        global_selected_index: usize,
+        app_state: &AppState,
+        scroll: &mut ScrollOffsets,
     ) {
         let items: Vec<ListItem> = tasks
             .iter()
-            .map(|task| {
-                let status_symbol = if task.is_completed { "✓" } else { " " };
-
-                // Process markdown from both content and description.
-                let content_md = parse_markdown(&task.content);
-                let desc_md = parse_markdown(&task.description);
-                let desc_truncated = if !desc_md.is_empty() {
-                    if desc_md.len() > 100 {
-                        format!(" - {}...", &desc_md[..100])
-                    } else {
-                        format!(" - {}", desc_md)
-                    }
-                } else {
-                    String::new()
-                };
-
-                let combined = format!("[{}] {}{}", status_symbol, content_md, desc_truncated);
-                let style = if task.is_completed {
-                    Style::default()
-                        .fg(Color::DarkGray)
-                        .add_modifier(Modifier::CROSSED_OUT)
-                } else {
-                    Style::default()
-                };
-                ListItem::new(Line::from(Span::styled(combined, style)))
-            })
+            .map(|task| Self::task_list_item(task, tasks, app_state))
             .collect();
 
         // Compute local selection index for this section if needed:
+        let global_selected_index = app_state.selected_index;
         let local_selected =
             if global_selected_index >= offset && global_selected_index < offset + tasks.len() {
                 Some(global_selected_index - offset)
@@ -284,9 +903,22 @@ impl UI {
                 None
             };
 
-        // Create a temporary ListState for this section:
-        let mut section_state = ListState::default();
-        section_state.select(local_selected);
+        // Start from where the section was scrolled to last time. A list
+        // with nothing selected would scroll back to its first task, so it is
+        // shown from its offset instead.
+        let scrolled = scroll.offset(title, tasks.len(), area);
+        let (items, mut section_state) = match local_selected {
+            Some(selected) => (
+                items,
+                ListState::default()
+                    .with_selected(Some(selected))
+                    .with_offset(scrolled),
+            ),
+            None => (
+                items.into_iter().skip(scrolled).collect(),
+                ListState::default(),
+            ),
+        };
 
         let list = List::new(items)
             .block(Block::default().borders(Borders::ALL).title(title))
@@ -298,13 +930,127 @@ impl UI {
             .highlight_symbol("> ");
 
         f.render_stateful_widget(list, area, &mut section_state);
+        if local_selected.is_some() {
+            scroll.set(title, section_state.offset());
+        }
     }
 
     fn render_status_bar(f: &mut Frame, area: ratatui::layout::Rect, app_state: &AppState) {
+        // An open prompt takes over the status bar.
+        let prompt = match &app_state.input_mode {
+            InputMode::Normal
+            | InputMode::EditTask
+            | InputMode::ResolveConflict
+            | InputMode::ExternalEditor(_) => None,
+            InputMode::AddTask => Some("Add task (#project @label p1-p4 due date)"),
+            InputMode::Command => Some("Command (filter <query>, save <name>, unsave, token)"),
+            InputMode::EnterToken => Some("Todoist rejected the API token; enter a new one"),
+            InputMode::ConfirmDelete(task_id) => {
+                let content = app_state
+                    .tasks
+                    .iter()
+                    .chain(app_state.completed_tasks.iter())
+                    .chain(app_state.view_tasks.iter())
+                    .find(|t| t.id == *task_id)
+                    .map(|t| t.content.as_str())
+                    .unwrap_or_default();
+                let text = match app_state.subtask_ids(task_id).len() {
+                    0 => format!("Delete \"{}\"? (y/n)", parse_markdown(content)),
+                    subtasks => format!(
+                        "Delete \"{}\" and its {} subtask(s)? (y/n)",
+                        parse_markdown(content),
+                        subtasks
+                    ),
+                };
+                let paragraph = Paragraph::new(text)
+                    .style(Style::default().fg(Color::Red))
+                    .block(Block::default().borders(Borders::ALL));
+                f.render_widget(paragraph, area);
+                return;
+            }
+            InputMode::ConfirmComplete(task_id) => {
+                let content = app_state
+                    .tasks
+                    .iter()
+                    .chain(app_state.view_tasks.iter())
+                    .find(|t| t.id == *task_id)
+                    .map(|t| t.content.as_str())
+                    .unwrap_or_default();
+                let text = format!(
+                    "Complete \"{}\" and its {} open subtask(s)? (y/n)",
+                    parse_markdown(content),
+                    app_state.open_subtask_ids(task_id).len()
+                );
+                let paragraph = Paragraph::new(text)
+                    .style(Style::default().fg(Color::Yellow))
+                    .block(Block::default().borders(Borders::ALL));
+                f.render_widget(paragraph, area);
+                return;
+            }
+            InputMode::ConfirmReschedule(days) => {
+                let text = format!(
+                    "Move {} overdue task(s) to {}? (y/n)",
+                    app_state.overdue_count(),
+                    if *days == 0 { "today" } else { "tomorrow" }
+                );
+                let paragraph = Paragraph::new(text)
+                    .style(Style::default().fg(Color::Yellow))
+                    .block(Block::default().borders(Borders::ALL));
+                f.render_widget(paragraph, area);
+                return;
+            }
+        };
+        if let Some(prompt) = prompt {
+            let input = if app_state.input_mode == InputMode::EnterToken {
+                "*".repeat(app_state.input_buffer.chars().count())
+            } else {
+                app_state.input_buffer.clone()
+            };
+            let content = format!("{}: {}", prompt, input);
+            let paragraph = Paragraph::new(content.clone()).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Enter: save, Esc: cancel"),
+            );
+            f.render_widget(paragraph, area);
+            f.set_cursor(area.x + 1 + content.chars().count() as u16, area.y + 1);
+            return;
+        }
+
         let status_text = match &app_state.sync_status {
-            crate::state::SyncStatus::Online => "Online".to_string(),
-            crate::state::SyncStatus::Offline => "Offline".to_string(),
-            crate::state::SyncStatus::Syncing => format!("{} Syncing...", spinner_frame()),
+            crate::state::SyncStatus::Online => match app_state.refreshed_at {
+                Some(synced_at) => format!(
+                    "Online (synced {})",
+                    format_age(chrono::Utc::now() - synced_at)
+                ),
+                None => "Online".to_string(),
+            },
+            crate::state::SyncStatus::Offline => match app_state.refreshed_at {
+                Some(synced_at) => format!(
+                    "Offline (synced {})",
+                    format_age(chrono::Utc::now() - synced_at)
+                ),
+                None => "Offline".to_string(),
+            },
+            crate::state::SyncStatus::Syncing => match app_state.retrying_at {
+                Some(retrying_at) => format!(
+                    "{} Syncing... (retrying in {}s)",
+                    spinner_frame(),
+                    retrying_at
+                        .saturating_duration_since(std::time::Instant::now())
+                        .as_secs()
+                ),
+                None => format!("{} Syncing...", spinner_frame()),
+            },
+            crate::state::SyncStatus::Unauthorized => {
+                "Token rejected (:token to enter a new one)".to_string()
+            }
+            crate::state::SyncStatus::RateLimited {
+                retry_after: Some(retry_after),
+            } => format!("Rate limited (retry in {}s)", retry_after.as_secs()),
+            crate::state::SyncStatus::RateLimited { retry_after: None } => {
+                "Rate limited".to_string()
+            }
             crate::state::SyncStatus::Error(e) => format!("ERR: {}", e),
         };
 
@@ -314,9 +1060,25 @@ impl UI {
             String::new()
         };
 
+        let pending_text = match app_state.time_until_next_flush() {
+            Some(remaining) => format!(
+                " | Pending: {} (sync in {}s)",
+                app_state.pending_changes.len(),
+                remaining.as_secs()
+            ),
+            None => String::new(),
+        };
+
+        let conflicts_text = match app_state.conflicts.len() {
+            0 => String::new(),
+            n => format!(" | Conflicts: {} (c: resolve)", n),
+        };
+
         let content = format!(
-            "Status: {}{} | Tasks: {} | q: quit, r: refresh, j/k: move, space: (un)check",
+            "Status: {}{}{}{} | Tasks: {} | q: quit, r: refresh, j/k: move, h/l: views, :: filter, space: (un)check, a: add, e/E: edit (form/$EDITOR), za: fold, dd: delete, u/^r: undo/redo",
             status_text,
+            pending_text,
+            conflicts_text,
             search_text,
             app_state.tasks.len()
         );
@@ -326,6 +1088,71 @@ impl UI {
         f.render_widget(paragraph, area);
     }
 
+    /// Open a task's content and description in `$EDITOR` as markdown,
+    /// returning the edited pair, or `None` if the editor failed.
+    ///
+    /// The terminal is handed over to the editor for the duration and always
+    /// restored afterwards, whatever the editor did.
+    ///
+    /// The file gets a random name and must not already exist, so nothing
+    /// else in the temp dir can be written through it.
+    async fn edit_in_external_editor(
+        &mut self,
+        task: &Task,
+    ) -> io::Result<Option<(String, String)>> {
+        let path = std::env::temp_dir().join(format!("tuidoist-{}.md", Uuid::new_v4()));
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let written = write!(file, "# {}\n\n{}\n", task.content, task.description);
+        drop(file);
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+
+        if let Err(e) = self.cleanup() {
+            let _ = self.restore();
+            let _ = std::fs::remove_file(&path);
+            return Err(io::Error::other(e.to_string()));
+        }
+        // The editor can stay open for minutes; keep it off the runtime's
+        // worker threads.
+        let editor_path = path.clone();
+        let status = tokio::task::spawn_blocking(move || run_editor(&editor_path))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        let restored = self.restore();
+
+        let result = match status {
+            Ok(status) if status.success() => std::fs::read_to_string(&path).map(|text| {
+                let (content, description) = parse_editor_markdown(&text);
+                // An emptied title almost certainly isn't meant as a rename.
+                (!content.is_empty()).then_some((content, description))
+            }),
+            Ok(status) => {
+                log::warn!("Editor exited with {}", status);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        };
+        let _ = std::fs::remove_file(&path);
+        restored?;
+        result
+    }
+
+    /// Re-enter the alternate screen after `cleanup`, forcing a full redraw.
+    fn restore(&mut self) -> io::Result<()> {
+        enable_raw_mode()?;
+        execute!(
+            self.terminal.backend_mut(),
+            EnterAlternateScreen,
+            EnableMouseCapture
+        )?;
+        self.terminal.clear()
+    }
+
     fn cleanup(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         disable_raw_mode()?;
         execute!(
@@ -0,0 +1,56 @@
+//! Saved filter views
+//!
+//! Handles:
+//! - Persisting named Todoist filter queries under the XDG config dir
+//! - Loading them on startup so they show up in the sidebar
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const VIEWS_FILE_NAME: &str = "views.json";
+
+/// A Todoist filter query saved under a name, e.g. "Work" for `p1 & #Work`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SavedView {
+    pub name: String,
+    pub query: String,
+}
+
+fn views_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tuidoist").join(VIEWS_FILE_NAME))
+}
+
+/// Load the saved views, or none if there is no readable file.
+pub fn load() -> Vec<SavedView> {
+    let Some(path) = views_path() else {
+        return Vec::new();
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    match serde_json::from_str(&contents) {
+        Ok(views) => views,
+        Err(e) => {
+            log::warn!("Ignoring unreadable views at {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// Write the saved views to disk.
+pub fn save(views: &[SavedView]) -> io::Result<()> {
+    let path = views_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_string_pretty(views)?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json)?;
+    fs::rename(&tmp_path, &path)?;
+    log::debug!("Saved {} views to {}", views.len(), path.display());
+    Ok(())
+}